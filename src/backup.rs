@@ -0,0 +1,354 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+const SHARED_DIR: &str = "shared";
+const META_DIR: &str = "meta";
+const COPY_BUFFER_SIZE: usize = 1 << 16;
+
+/// A file of a backup, with the crc32 of its content.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackupFile {
+    pub name: String,
+    pub size: u64,
+    pub checksum: u32,
+}
+
+/// A numbered backup and the files it is made of.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub id: u32,
+    pub files: Vec<BackupFile>,
+}
+
+/// Keeps incremental backups of the SST files of a database directory.
+///
+/// Every SST file is checksummed when it is backed up, and copied into `shared/` as
+/// `<name>.<size>.<checksum>` unless an earlier backup already holds that copy, which every backup
+/// containing the file then references. A file that changed under the same name is copied again
+/// instead of overwriting the copy older backups reference. Files are streamed, never loaded
+/// whole into memory. Each backup is described by `meta/<id>`, with one
+/// `<name> <size> <checksum>` line per file. A backup only becomes visible once its meta file is
+/// written, so an interrupted backup leaves at most unreferenced shared files behind, which are
+/// removed when old backups are purged.
+pub struct BackupEngine {
+    dir: PathBuf,
+}
+
+impl BackupEngine {
+    /// Open the backup directory, creating it if needed.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join(SHARED_DIR))?;
+        fs::create_dir_all(dir.join(META_DIR))?;
+        Ok(Self { dir })
+    }
+
+    /// Back up the SST files of `db_dir`, copying only the files no earlier backup holds with the
+    /// same content. Returns the id of the new backup.
+    pub fn create_backup(&self, db_dir: impl AsRef<Path>) -> Result<u32> {
+        let id = self.read_backups()?.keys().next_back().map_or(1, |id| id + 1);
+
+        let mut files = vec![];
+        for name in list_sst_files(db_dir.as_ref())? {
+            let src = db_dir.as_ref().join(&name);
+            // Every file is checksummed, since a file rewritten under the same name may keep its
+            // size.
+            let (size, checksum) = copy_checksummed(&src, io::sink())?;
+            let file = BackupFile { name, size, checksum };
+            if !self.shared_path(&file).exists() {
+                copy_file_atomic(&src, &self.shared_path(&file), &file)
+                    .map_err(|e| anyhow!("cannot back up {}: {}", file.name, e))?;
+            }
+            files.push(file);
+        }
+
+        let mut meta = String::new();
+        for file in &files {
+            meta.push_str(&format!("{} {} {}\n", file.name, file.size, file.checksum));
+        }
+        write_file_atomic(&self.meta_path(id), meta.as_bytes())?;
+        Ok(id)
+    }
+
+    /// List the backups, oldest first.
+    pub fn list_backups(&self) -> Result<Vec<BackupInfo>> {
+        Ok(self.read_backups()?.into_values().collect())
+    }
+
+    /// Delete a backup, and the shared files no other backup references.
+    pub fn delete_backup(&self, id: u32) -> Result<()> {
+        fs::remove_file(self.meta_path(id)).map_err(|e| anyhow!("cannot delete backup {}: {}", id, e))?;
+        self.remove_unreferenced_files()
+    }
+
+    /// Delete all but the `num_to_keep` newest backups.
+    pub fn purge_old_backups(&self, num_to_keep: usize) -> Result<()> {
+        let backups = self.read_backups()?;
+        let num_to_delete = backups.len().saturating_sub(num_to_keep);
+        for id in backups.keys().take(num_to_delete) {
+            fs::remove_file(self.meta_path(*id))?;
+        }
+        self.remove_unreferenced_files()
+    }
+
+    /// Check the files of a backup against their recorded sizes and checksums.
+    pub fn verify_backup(&self, id: u32) -> Result<()> {
+        for file in self.read_backup(id)?.files {
+            let (size, checksum) = copy_checksummed(&self.shared_path(&file), io::sink())?;
+            if (size, checksum) != (file.size, file.checksum) {
+                return Err(anyhow!("backup file {} is corrupted", file.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Restore a backup into `db_dir`, which must not contain any SST file yet. Every file is
+    /// verified against its checksum before it is renamed into place.
+    pub fn restore(&self, id: u32, db_dir: impl AsRef<Path>) -> Result<()> {
+        let db_dir = db_dir.as_ref();
+        fs::create_dir_all(db_dir)?;
+        if !list_sst_files(db_dir)?.is_empty() {
+            return Err(anyhow!("cannot restore backup {} into non-empty directory {:?}", id, db_dir));
+        }
+        for file in self.read_backup(id)?.files {
+            copy_file_atomic(&self.shared_path(&file), &db_dir.join(&file.name), &file)
+                .map_err(|e| anyhow!("cannot restore {}: {}", file.name, e))?;
+        }
+        Ok(())
+    }
+
+    fn read_backup(&self, id: u32) -> Result<BackupInfo> {
+        let meta = fs::read_to_string(self.meta_path(id)).map_err(|e| anyhow!("cannot read backup {}: {}", id, e))?;
+        let mut files = vec![];
+        for line in meta.lines() {
+            let fields: Vec<&str> = line.split(' ').collect();
+            let file = match fields[..] {
+                [name, size, checksum] => BackupFile {
+                    name: name.to_string(),
+                    size: size.parse()?,
+                    checksum: checksum.parse()?,
+                },
+                _ => return Err(anyhow!("bad line {:?} in meta of backup {}", line, id)),
+            };
+            files.push(file);
+        }
+        Ok(BackupInfo { id, files })
+    }
+
+    fn read_backups(&self) -> Result<BTreeMap<u32, BackupInfo>> {
+        let mut backups = BTreeMap::new();
+        for entry in fs::read_dir(self.dir.join(META_DIR))? {
+            let name = entry?.file_name();
+            // Skip the temporary files of unfinished backups.
+            if let Some(id) = name.to_str().and_then(|name| name.parse::<u32>().ok()) {
+                backups.insert(id, self.read_backup(id)?);
+            }
+        }
+        Ok(backups)
+    }
+
+    fn remove_unreferenced_files(&self) -> Result<()> {
+        let referenced: HashSet<String> = self
+            .read_backups()?
+            .into_values()
+            .flat_map(|b| b.files)
+            .map(|f| shared_name(&f))
+            .collect();
+        for entry in fs::read_dir(self.dir.join(SHARED_DIR))? {
+            let entry = entry?;
+            if !referenced.contains(entry.file_name().to_string_lossy().as_ref()) {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn shared_path(&self, file: &BackupFile) -> PathBuf {
+        self.dir.join(SHARED_DIR).join(shared_name(file))
+    }
+
+    fn meta_path(&self, id: u32) -> PathBuf {
+        self.dir.join(META_DIR).join(id.to_string())
+    }
+}
+
+/// Name of the copy of `file` in `shared/`.
+fn shared_name(file: &BackupFile) -> String {
+    format!("{}.{}.{}", file.name, file.size, file.checksum)
+}
+
+/// Names of the SST files in `dir`, sorted.
+fn list_sst_files(dir: &Path) -> Result<Vec<String>> {
+    let mut names = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "sst") {
+            names.push(path.file_name().unwrap().to_string_lossy().into_owned());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Stream `src` into `dst`, returning the size and the crc32 of the content.
+fn copy_checksummed(src: &Path, mut dst: impl Write) -> Result<(u64, u32)> {
+    let mut reader = fs::File::open(src)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0; COPY_BUFFER_SIZE];
+    let mut size = 0;
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        dst.write_all(&buf[..n])?;
+        size += n as u64;
+    }
+    Ok((size, hasher.finalize()))
+}
+
+/// Copy `src` to a temporary file, check that its content matches `file`, sync it and rename it
+/// to `dst`.
+fn copy_file_atomic(src: &Path, dst: &Path, file: &BackupFile) -> Result<()> {
+    let tmp = dst.with_extension("tmp");
+    let mut writer = fs::File::create(&tmp)?;
+    let (size, checksum) = copy_checksummed(src, &mut writer)?;
+    if (size, checksum) != (file.size, file.checksum) {
+        drop(writer);
+        fs::remove_file(&tmp)?;
+        return Err(anyhow!("content of {:?} does not match its checksum", src));
+    }
+    writer.sync_all()?;
+    fs::rename(&tmp, dst)?;
+    Ok(())
+}
+
+/// Write `data` to a temporary file, sync it and rename it to `path`.
+fn write_file_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::backup::BackupEngine;
+    use crate::table::FileObject;
+    use crate::table::SsTable;
+
+    fn write_sst(dir: &std::path::Path, name: &str, seed: usize) {
+        let mut writer = crate::SstFileWriter::create(dir.join(name), 64).unwrap();
+        for i in 0..50 {
+            writer.put(format!("key_{:03}", i).as_bytes(), format!("value_{}_{}", seed, i).as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn test_incremental_backup_and_restore() {
+        let db = tempdir().unwrap();
+        let backup_dir = tempdir().unwrap();
+        let engine = BackupEngine::open(backup_dir.path()).unwrap();
+
+        write_sst(db.path(), "1.sst", 1);
+        write_sst(db.path(), "2.sst", 2);
+        let first = engine.create_backup(db.path()).unwrap();
+        let shared_1 = engine.shared_path(&engine.list_backups().unwrap()[0].files[0]);
+        let modified = fs::metadata(&shared_1).unwrap().modified().unwrap();
+
+        fs::remove_file(db.path().join("1.sst")).unwrap();
+        write_sst(db.path(), "3.sst", 3);
+        let second = engine.create_backup(db.path()).unwrap();
+        assert_eq!((first, second), (1, 2));
+        // Files already backed up are not copied again.
+        assert_eq!(fs::metadata(&shared_1).unwrap().modified().unwrap(), modified);
+
+        let backups = engine.list_backups().unwrap();
+        let names: Vec<Vec<&str>> = backups.iter().map(|b| b.files.iter().map(|f| f.name.as_str()).collect()).collect();
+        assert_eq!(names, vec![vec!["1.sst", "2.sst"], vec!["2.sst", "3.sst"]]);
+        engine.verify_backup(first).unwrap();
+        engine.verify_backup(second).unwrap();
+
+        let restored = tempdir().unwrap();
+        engine.restore(first, restored.path()).unwrap();
+        assert_eq!(fs::read(restored.path().join("1.sst")).unwrap(), fs::read(&shared_1).unwrap());
+        let sst = SsTable::open(0, FileObject::open(&restored.path().join("2.sst")).unwrap(), None).unwrap();
+        assert_eq!(sst.properties().num_entries, 50);
+        assert!(engine.restore(second, restored.path()).is_err());
+
+        engine.purge_old_backups(1).unwrap();
+        assert_eq!(engine.list_backups().unwrap().len(), 1);
+        assert!(!shared_1.exists());
+        assert!(engine.verify_backup(first).is_err());
+        engine.verify_backup(second).unwrap();
+    }
+
+    #[test]
+    fn test_verify_corrupted_backup() {
+        let db = tempdir().unwrap();
+        let backup_dir = tempdir().unwrap();
+        let engine = BackupEngine::open(backup_dir.path()).unwrap();
+        write_sst(db.path(), "1.sst", 1);
+        let id = engine.create_backup(db.path()).unwrap();
+
+        let shared = engine.shared_path(&engine.list_backups().unwrap()[0].files[0]);
+        let mut data = fs::read(&shared).unwrap();
+        data[10] ^= 0xff;
+        fs::write(&shared, data).unwrap();
+        assert!(engine.verify_backup(id).is_err());
+        assert!(engine.restore(id, tempdir().unwrap().path()).is_err());
+    }
+
+    #[test]
+    fn test_backup_file_changed_under_same_name() {
+        let db = tempdir().unwrap();
+        let backup_dir = tempdir().unwrap();
+        let engine = BackupEngine::open(backup_dir.path()).unwrap();
+        write_sst(db.path(), "1.sst", 1);
+        let first = engine.create_backup(db.path()).unwrap();
+        let old = fs::read(db.path().join("1.sst")).unwrap();
+
+        fs::remove_file(db.path().join("1.sst")).unwrap();
+        let mut writer = crate::SstFileWriter::create(db.path().join("1.sst"), 64).unwrap();
+        writer.put(b"key", b"value").unwrap();
+        writer.finish().unwrap();
+        let second = engine.create_backup(db.path()).unwrap();
+
+        // The older backup still holds the old content.
+        engine.verify_backup(first).unwrap();
+        engine.verify_backup(second).unwrap();
+        let restored = tempdir().unwrap();
+        engine.restore(first, restored.path()).unwrap();
+        assert_eq!(fs::read(restored.path().join("1.sst")).unwrap(), old);
+
+        engine.delete_backup(first).unwrap();
+        engine.verify_backup(second).unwrap();
+        assert_eq!(fs::read_dir(backup_dir.path().join("shared")).unwrap().count(), 1);
+
+        // A rewrite that keeps the size is backed up too.
+        write_sst(db.path(), "2.sst", 1);
+        let third = engine.create_backup(db.path()).unwrap();
+        let old = fs::read(db.path().join("2.sst")).unwrap();
+        fs::remove_file(db.path().join("2.sst")).unwrap();
+        write_sst(db.path(), "2.sst", 2);
+        let new = fs::read(db.path().join("2.sst")).unwrap();
+        assert_eq!(old.len(), new.len());
+        let fourth = engine.create_backup(db.path()).unwrap();
+        engine.verify_backup(third).unwrap();
+        let restored = tempdir().unwrap();
+        engine.restore(fourth, restored.path()).unwrap();
+        assert_eq!(fs::read(restored.path().join("2.sst")).unwrap(), new);
+    }
+}
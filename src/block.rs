@@ -6,10 +6,13 @@ pub mod iterator;
 mod tests;
 pub mod block_builder;
 
+const SIZE_OF_U16: usize = std::mem::size_of::<u16>();
+const SIZE_OF_U32: usize = std::mem::size_of::<u32>();
+
 #[derive(Default, Debug)]
 pub struct Block {
     data: Vec<u8>,
-    offsets: Vec<u16>,
+    offsets: Vec<u32>,
     //crc:u32
 }
 
@@ -20,33 +23,55 @@ impl fmt::Display for Block {
 }
 
 impl Block {
+    /// Encode a data block, whose offsets and entry count are u16. A data block is cut at the
+    /// target block size, so its entries always start within the first 64 KiB.
     pub fn encode(&self) -> Bytes {
-        let mut block = BytesMut::with_capacity(self.block_size());
+        self.encode_with(SIZE_OF_U16)
+    }
+
+    /// Encode an index or meta block, whose offsets and entry count are u32. Unlike data blocks,
+    /// these blocks grow with the table and are not bounded by the block size.
+    pub fn encode_wide(&self) -> Bytes {
+        self.encode_with(SIZE_OF_U32)
+    }
+
+    fn encode_with(&self, width: usize) -> Bytes {
+        let mut block = BytesMut::with_capacity(self.block_size(width));
         block.put(&self.data[..]);
         for off in &self.offsets {
-            block.put_u16(*off);
+            block.put_uint(*off as u64, width);
         }
-        block.put_u16(self.offsets.len() as u16);
+        block.put_uint(self.offsets.len() as u64, width);
         let buf = block.freeze();
         //println!("encode {:?} block size {}", buf, buf.len());
         buf
     }
 
+    /// Decode a data block, see `encode`.
     pub fn decode(data: &[u8]) -> Self {
-        if data.len() <= 2 {
+        Self::decode_with(data, SIZE_OF_U16)
+    }
+
+    /// Decode an index or meta block, see `encode_wide`.
+    pub fn decode_wide(data: &[u8]) -> Self {
+        Self::decode_with(data, SIZE_OF_U32)
+    }
+
+    fn decode_with(data: &[u8], width: usize) -> Self {
+        if data.len() <= width {
             return Self::default();
         }
 
-        let num = (&data[data.len() - 2..data.len()]).get_u16() as usize;
-        if data.len() <= 2 + num * 2 {
+        let num = (&data[data.len() - width..data.len()]).get_uint(width) as usize;
+        if data.len() <= width + num * width {
             return Self::default();
         }
-        let data = &data[..data.len() - 2];
+        let data = &data[..data.len() - width];
         let mut offs = Vec::with_capacity(num);
-        let mut off_buf = &data[data.len() - num * 2..];
-        let data_buf = &data[..data.len() - num * 2];
+        let mut off_buf = &data[data.len() - num * width..];
+        let data_buf = &data[..data.len() - num * width];
         while off_buf.has_remaining() {
-            offs.push(off_buf.get_u16());
+            offs.push(off_buf.get_uint(width) as u32);
         }
 
         Self {
@@ -56,15 +81,35 @@ impl Block {
     }
 
 
-    fn block_size(&self) -> usize {
+    fn block_size(&self, width: usize) -> usize {
         let mut size = self.data.len();
-        size += self.offsets.len() * 2;
-        size += 2;
+        size += self.offsets.len() * width;
+        size += width;
         size
     }
 
+    /// Number of key-value pairs in the block.
+    pub fn num_of_entries(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Returns the key of the `idx`-th entry without copying it out of the block.
+    pub fn key_at(&self, idx: usize) -> &[u8] {
+        let mut entry = &self.data[self.offsets[idx] as usize..];
+        let key_len = entry.get_u16() as usize;
+        &entry[..key_len]
+    }
+
+    /// Returns the value of the `idx`-th entry without copying it out of the block.
+    pub fn value_at(&self, idx: usize) -> &[u8] {
+        let mut entry = &self.data[self.offsets[idx] as usize..];
+        let key_len = entry.get_u16() as usize;
+        entry.advance(key_len);
+        let val_len = entry.get_u16() as usize;
+        &entry[..val_len]
+    }
 
-    pub fn new(data: Vec<u8>, offsets: Vec<u16>) -> Self {
+    pub fn new(data: Vec<u8>, offsets: Vec<u32>) -> Self {
         Self { data, offsets }
     }
 }
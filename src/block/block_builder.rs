@@ -2,13 +2,15 @@ use bytes::BufMut;
 
 use crate::block::Block;
 
-static SIZE_OF_META: usize = 2;
+use super::{SIZE_OF_U16, SIZE_OF_U32};
 
 /// Builds a block.
 pub struct BlockBuilder {
     data: Vec<u8>,
-    offsets: Vec<u16>,
+    offsets: Vec<u32>,
     capacity: usize,
+    /// Size of the offsets and the entry count, see `Block::encode_wide`.
+    width: usize,
 }
 
 impl BlockBuilder {
@@ -18,6 +20,18 @@ impl BlockBuilder {
             data: Vec::with_capacity(block_size),
             offsets: vec![],
             capacity: block_size,
+            width: SIZE_OF_U16,
+        }
+    }
+
+    /// Creates a block builder without a size limit, used for index and meta blocks. The block
+    /// must be encoded with `Block::encode_wide`.
+    pub fn new_unbounded() -> Self {
+        Self {
+            data: Vec::new(),
+            offsets: vec![],
+            capacity: usize::MAX,
+            width: SIZE_OF_U32,
         }
     }
 
@@ -26,7 +40,12 @@ impl BlockBuilder {
         if key.is_empty() {
             return false;
         }
-        if self.cur_size() + key.len() + value.len() + 4 > self.capacity {
+        // The first entry is always accepted, so an oversized pair still gets its own block.
+        if !self.is_empty() && self.cur_size() + key.len() + value.len() + 4 + self.width > self.capacity {
+            return false;
+        }
+        // The offset of the entry must fit in the offset width of the block.
+        if self.width == SIZE_OF_U16 && self.data.len() > u16::MAX as usize {
             return false;
         }
         // check order todo
@@ -35,7 +54,7 @@ impl BlockBuilder {
              let last_put_entry = &self.data[last_off..];
              []
          }*/
-        self.offsets.push(self.data.len() as u16);
+        self.offsets.push(self.data.len() as u32);
         self.data.put_u16(key.len() as u16);
         self.data.put_slice(key);
         self.data.put_u16(value.len() as u16);
@@ -52,6 +71,6 @@ impl BlockBuilder {
     }
 
     pub fn cur_size(&self) -> usize {
-        self.data.len() + self.offsets.len() * 2 + SIZE_OF_META
+        self.data.len() + (self.offsets.len() + 1) * self.width
     }
 }
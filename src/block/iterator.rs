@@ -7,6 +7,7 @@ use std::sync::Arc;
 use bytes::{Buf, BufMut};
 
 use crate::block::Block;
+use crate::skip_list::{BytewiseComparator, KeyComparator};
 
 /// Iterates on a block.
 pub struct BlockIterator {
@@ -39,8 +40,13 @@ impl BlockIterator {
 
     /// Creates a block iterator and seek to the first key that >= `key`.
     pub fn create_and_seek_to_key(block: Arc<Block>, key: &[u8]) -> Self {
+        Self::create_and_seek_to_key_with(block, key, &BytewiseComparator)
+    }
+
+    /// Creates a block iterator and seek to the first key that >= `key` in the order of `c`.
+    pub fn create_and_seek_to_key_with<C: KeyComparator>(block: Arc<Block>, key: &[u8], c: &C) -> Self {
         let mut iterator = Self::new(block);
-        iterator.seek_to_key(key);
+        iterator.seek_to_key_with(key, c);
         iterator
     }
 
@@ -80,13 +86,19 @@ impl BlockIterator {
     /// Seek to the first key that >= `key`.
     /// Note: You should assume the key-value pairs in the block are sorted when being added by callers.
     pub fn seek_to_key(&mut self, key: &[u8]) {
+        self.seek_to_key_with(key, &BytewiseComparator)
+    }
+
+    /// Seek to the first key that >= `key` in the order of `c`, which must be the order the block
+    /// was built in.
+    pub fn seek_to_key_with<C: KeyComparator>(&mut self, key: &[u8], c: &C) {
         let mut low = 0;
         let mut high = self.block.offsets.len();
         while low < high {
             let mid = low + (high - low) / 2;
             self.seek_to(mid);
             assert!(self.is_valid());
-            match self.key().cmp(key) {
+            match c.compare_key(self.key(), key) {
                 std::cmp::Ordering::Less => low = mid + 1,
                 std::cmp::Ordering::Greater => high = mid,
                 std::cmp::Ordering::Equal => return,
@@ -62,6 +62,30 @@ fn test_block_decode() {
     assert_eq!(block.data, decoded_block.data);
 }
 
+#[test]
+fn test_block_decode_wide() {
+    let block = generate_block();
+    let (narrow, wide) = (block.encode(), block.encode_wide());
+    assert_eq!(wide.len() - narrow.len(), (num_of_keys() + 1) * 2);
+    let decoded_block = Block::decode_wide(&wide);
+    assert_eq!(block.offsets, decoded_block.offsets);
+    assert_eq!(block.data, decoded_block.data);
+}
+
+#[test]
+fn test_block_offsets_fit_u16() {
+    let mut builder = BlockBuilder::new(1 << 20);
+    let value = vec![0; 1000];
+    let mut num = 0;
+    while builder.add(&key_of(num), &value) {
+        num += 1;
+    }
+    let block = builder.build();
+    assert_eq!(block.num_of_entries(), num);
+    assert!(block.offsets.iter().all(|off| *off <= u16::MAX as u32));
+    assert_eq!(Block::decode(&block.encode()).offsets, block.offsets);
+}
+
 fn as_bytes(x: &[u8]) -> Bytes {
     Bytes::copy_from_slice(x)
 }
@@ -4,11 +4,20 @@ use std::collections::Bound;
 
 use bytes::Bytes;
 
+mod backup;
 pub mod block;
 mod table;
 mod iterators;
 mod skip_list;
 mod memtable;
+mod merge_operator;
+mod range_tombstone;
+
+pub use backup::{BackupEngine, BackupFile, BackupInfo};
+pub use merge_operator::{BytesAppendOperator, collapse_operands, MergeOperator, U64AddOperator};
+pub use range_tombstone::RangeTombstone;
+pub use skip_list::{AllocError, BytewiseComparator, KeyComparator};
+pub use table::{SstFileWriter, TableProperties, validate_external_file};
 
 pub fn add(left: usize, right: usize) -> usize {
     left + right
@@ -1,77 +1,289 @@
 use std::ops::Bound;
+use std::sync::RwLock;
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use bytes::Bytes;
 
 use crate::iterators::StorageIterator;
 use crate::map_bound;
-use crate::skip_list::{KeyComparator, RangeRef, Skiplist};
+use crate::range_tombstone::RangeTombstone;
+use crate::skip_list::{Arena, KeyComparator, Skiplist};
+
+pub use rep::{HashLinkListRep, MemTableRep, MemTableRepIter, MemTableRepKind, VectorRep};
+
+mod rep;
+mod tombstones;
+
+use tombstones::RangeTombstones;
 
 pub struct MemTable<C: KeyComparator> {
-    skl: Skiplist<C>,
+    rep: Box<dyn MemTableRep<C>>,
     id: usize,
+    /// Range tombstones, which hide the keys put into their range before them. A put cuts its key
+    /// out of the tombstones, so they never hide a newer key.
+    range_tombstones: RwLock<RangeTombstones<C>>,
 }
 
-impl<C: KeyComparator> MemTable<C> {
+impl<C: KeyComparator + Clone + Send + Sync + 'static> MemTable<C> {
     pub fn new(cap: usize, c: C) -> Self {
+        Self::with_rep(MemTableRepKind::SkipList, cap, c)
+    }
+
+    /// Create a memtable on a chained arena, which grows by chunks of `chunk_size` bytes up to
+    /// `max_chunks` chunks instead of allocating its whole capacity upfront.
+    pub fn new_chained(chunk_size: usize, max_chunks: usize, c: C) -> Self {
+        Self::from_rep(Box::new(Skiplist::with_arena(c, Arena::chained(chunk_size, max_chunks))))
+    }
+
+    /// Create a memtable that keeps its entries in the data structure selected by `kind`. `cap`
+    /// is the arena size of a skiplist, and is ignored by the other reps.
+    pub fn with_rep(kind: MemTableRepKind, cap: usize, c: C) -> Self {
+        let rep: Box<dyn MemTableRep<C>> = match kind {
+            MemTableRepKind::SkipList => Box::new(Skiplist::with_capacity(c, cap)),
+            MemTableRepKind::Vector => Box::new(VectorRep::new(c)),
+            MemTableRepKind::HashLinkList { prefix_len } => Box::new(HashLinkListRep::new(prefix_len, c)),
+        };
+        Self::from_rep(rep)
+    }
+
+    pub fn from_rep(rep: Box<dyn MemTableRep<C>>) -> Self {
+        let range_tombstones = RangeTombstones::new(rep.comparator().clone());
         Self {
-            skl: Skiplist::with_capacity(c, cap as u32),
+            rep,
             id: 0,
+            range_tombstones: RwLock::new(range_tombstones),
         }
     }
+}
 
+impl<C: KeyComparator + Clone> MemTable<C> {
     pub fn get(&self, key: &[u8]) -> Option<Bytes> {
-        self.skl.get(key).map(|v| v.clone())
+        if self.is_range_deleted(key) {
+            return None;
+        }
+        self.rep.get(key)
     }
 
+    /// Put a key-value pair. Fails with `AllocError::ArenaFull` when the memtable has no room left,
+    /// in which case it should be frozen and replaced by a new one, and with
+    /// `AllocError::EntryTooLarge` when the pair would not fit even in an empty one.
     pub fn put(&self, key: &[u8], val: &[u8]) -> Result<()> {
-        let r = self.skl.put(Bytes::copy_from_slice(key), Bytes::copy_from_slice(val));
-        match r {
-            None => { Ok(()) }
-            Some(_) => { Err(anyhow!("put item error")) }
+        let seq = self.range_tombstones.read().unwrap().next_seq;
+        self.rep.put(key, val)?;
+        self.cut_range_tombstones(key, seq);
+        Ok(())
+    }
+
+    /// Put key-value pairs sorted by key, e.g. for a bulk load. A skiplist starts each put from
+    /// where the previous one ended instead of searching from the head. On error, the pairs before
+    /// the failing one are put and the ones after it are not.
+    pub fn put_sorted_batch(&self, items: &[(&[u8], &[u8])]) -> Result<()> {
+        let seq = self.range_tombstones.read().unwrap().next_seq;
+        let (num_put, result) = self.rep.put_sorted_batch(items);
+        for (key, _) in &items[..num_put] {
+            self.cut_range_tombstones(key, seq);
         }
+        result
+    }
+
+    /// Stop taking writes, which lets the rep prepare for reads, e.g. sort a vector. Fails if the
+    /// rep finds a conflicting put it could not report earlier.
+    pub fn freeze(&self) -> Result<()> {
+        self.rep.freeze()
     }
 
-    pub fn scan(&self, left: Bound<&[u8]>, right: Bound<&[u8]>) -> MemTableIterator<C> {
+    /// Get the memory used by the entries.
+    pub fn mem_size(&self) -> usize {
+        self.rep.mem_size()
+    }
+
+    /// Delete every key in `[start, end)` that was put before.
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        let tombstone = RangeTombstone::new(start, end);
+        tombstone.validate(self.rep.comparator())?;
+        self.range_tombstones.write().unwrap().add(start, end);
+        Ok(())
+    }
+
+    /// Get the range tombstones as non-overlapping fragments sorted by start key, e.g. to write
+    /// them to the range deletion block of an SsTable.
+    pub fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        self.range_tombstones.read().unwrap().tombstones()
+    }
+
+    fn is_range_deleted(&self, key: &[u8]) -> bool {
+        self.range_tombstones.read().unwrap().covers(key)
+    }
+
+    /// Cut `key` out of the tombstones added before `seq`.
+    fn cut_range_tombstones(&self, key: &[u8], seq: u64) {
+        if !self.range_tombstones.read().unwrap().needs_cut(key, seq) {
+            return;
+        }
+        self.range_tombstones.write().unwrap().cut(key, seq);
+    }
+
+    pub fn scan(&self, left: Bound<&[u8]>, right: Bound<&[u8]>) -> MemTableIterator<'_, C> {
         let (lower, upper) = (map_bound(left), map_bound(right));
         MemTableIterator::create(self, lower, upper)
     }
+
+    /// Scan a range from its last key to its first one.
+    pub fn scan_rev(&self, left: Bound<&[u8]>, right: Bound<&[u8]>) -> MemTableRevIterator<'_, C> {
+        let (lower, upper) = (map_bound(left), map_bound(right));
+        MemTableRevIterator::create(self, lower, upper)
+    }
+}
+
+/// Whether `key` is past `bound` on the side given by `past`, e.g. `Greater` for an upper bound.
+fn is_past<C: KeyComparator>(c: &C, key: &[u8], bound: &Bound<Bytes>, past: std::cmp::Ordering) -> bool {
+    match bound {
+        Bound::Included(b) => c.compare_key(key, b) == past,
+        Bound::Excluded(b) => c.compare_key(key, b) != past.reverse(),
+        Bound::Unbounded => false,
+    }
 }
 
 pub struct MemTableIterator<'a, C: KeyComparator> {
-    iter: RangeRef<'a, C>,
-    item: (Bytes, Bytes),
+    iter: Box<dyn MemTableRepIter + 'a>,
+    upper: Bound<Bytes>,
+    /// Snapshot of the range tombstones overlapping the scanned range when the iterator was
+    /// created.
+    range_tombstones: RangeTombstones<C>,
+    c: &'a C,
 }
 
-impl<'a, C: KeyComparator> MemTableIterator<'a, C> {
+impl<'a, C: KeyComparator + Clone> MemTableIterator<'a, C> {
     pub fn create(mem_table: &'a MemTable<C>, lower: Bound<Bytes>, upper: Bound<Bytes>) -> Self {
-        Self {
-            iter: mem_table.skl.range_ref(lower, upper),
-            item: (Bytes::new(), Bytes::new()),
+        let c = mem_table.rep.comparator();
+        let mut iter = mem_table.rep.iter();
+        match &lower {
+            Bound::Included(key) => iter.seek(key),
+            Bound::Excluded(key) => {
+                iter.seek(key);
+                if iter.valid() && c.compare_key(iter.key(), key) == std::cmp::Ordering::Equal {
+                    iter.next();
+                }
+            }
+            Bound::Unbounded => iter.seek_to_first(),
+        }
+        let range_tombstones = mem_table.range_tombstones.read().unwrap().overlapping(&lower, &upper);
+        let mut mem_iter = Self {
+            iter,
+            upper,
+            range_tombstones,
+            c,
+        };
+        mem_iter.skip_range_deleted();
+        mem_iter
+    }
+
+    /// Skip the keys range deleted before the iterator was created, seeking past each fragment
+    /// instead of stepping over every key in it.
+    fn skip_range_deleted(&mut self) {
+        while self.is_valid() {
+            match self.range_tombstones.find(self.iter.key()) {
+                Some(fragment) => self.iter.seek(&fragment.tombstone.end),
+                None => return,
+            }
         }
     }
 }
 
-impl<'a, C: KeyComparator> StorageIterator for MemTableIterator<'a, C> {
+impl<'a, C: KeyComparator + Clone> StorageIterator for MemTableIterator<'a, C> {
     fn value(&self) -> &[u8] {
-        &self.item.1[..]
+        self.iter.value()
     }
 
     fn key(&self) -> &[u8] {
-        &self.item.0[..]
+        self.iter.key()
     }
 
     fn is_valid(&self) -> bool {
-        self.iter.valid()
+        self.iter.valid() && !is_past(self.c, self.iter.key(), &self.upper, std::cmp::Ordering::Greater)
     }
 
+    /// Move to the next key that is not range deleted.
     fn next(&mut self) -> Result<()> {
-        let kv = self.iter.next();
-        assert!(kv.is_some());
-        let kv = kv.unwrap();
-        let key = kv.0.clone();
-        let val = kv.1.clone();
-        self.item = (key, val);
+        self.iter.next();
+        self.skip_range_deleted();
+        Ok(())
+    }
+}
+
+/// Iterates over a range of a memtable in reverse order. With a skiplist, each step backwards is
+/// amortized O(1).
+pub struct MemTableRevIterator<'a, C: KeyComparator> {
+    iter: Box<dyn MemTableRepIter + 'a>,
+    lower: Bound<Bytes>,
+    /// Snapshot of the range tombstones overlapping the scanned range when the iterator was
+    /// created.
+    range_tombstones: RangeTombstones<C>,
+    c: &'a C,
+}
+
+impl<'a, C: KeyComparator + Clone> MemTableRevIterator<'a, C> {
+    pub fn create(mem_table: &'a MemTable<C>, lower: Bound<Bytes>, upper: Bound<Bytes>) -> Self {
+        let c = mem_table.rep.comparator();
+        let mut iter = mem_table.rep.iter();
+        match &upper {
+            Bound::Included(key) => iter.seek_for_prev(key),
+            Bound::Excluded(key) => {
+                iter.seek_for_prev(key);
+                if iter.valid() && c.compare_key(iter.key(), key) == std::cmp::Ordering::Equal {
+                    iter.prev();
+                }
+            }
+            Bound::Unbounded => iter.seek_to_last(),
+        }
+        let range_tombstones = mem_table.range_tombstones.read().unwrap().overlapping(&lower, &upper);
+        let mut rev_iter = Self {
+            iter,
+            lower,
+            range_tombstones,
+            c,
+        };
+        rev_iter.skip_range_deleted();
+        rev_iter
+    }
+
+    /// Skip the keys range deleted before the iterator was created, seeking before each fragment
+    /// instead of stepping over every key in it.
+    fn skip_range_deleted(&mut self) {
+        while self.is_valid() {
+            let (start, excluded) = match self.range_tombstones.find(self.iter.key()) {
+                Some(fragment) => match &fragment.tombstone.start {
+                    Bound::Included(start) => (start.clone(), false),
+                    Bound::Excluded(start) => (start.clone(), true),
+                    Bound::Unbounded => unreachable!("range tombstone without a start key"),
+                },
+                None => return,
+            };
+            self.iter.seek_for_prev(&start);
+            if !excluded && self.iter.valid() && self.c.compare_key(self.iter.key(), &start) == std::cmp::Ordering::Equal {
+                self.iter.prev();
+            }
+        }
+    }
+}
+
+impl<'a, C: KeyComparator + Clone> StorageIterator for MemTableRevIterator<'a, C> {
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn key(&self) -> &[u8] {
+        self.iter.key()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.valid() && !is_past(self.c, self.iter.key(), &self.lower, std::cmp::Ordering::Less)
+    }
+
+    /// Move to the previous key that is not range deleted.
+    fn next(&mut self) -> Result<()> {
+        self.iter.prev();
+        self.skip_range_deleted();
         Ok(())
     }
 }
@@ -80,14 +292,285 @@ impl<'a, C: KeyComparator> StorageIterator for MemTableIterator<'a, C> {
 mod test {
     use bytes::Bytes;
 
-    use crate::memtable::MemTable;
-    use crate::skip_list::FixedLengthSuffixComparator;
+    use std::ops::Bound;
+
+    use crate::iterators::StorageIterator;
+    use crate::memtable::{MemTable, MemTableRepKind};
+    use crate::skip_list::{AllocError, FixedLengthSuffixComparator, FlexibleCompartor, KeyComparator};
+
+    #[test]
+    fn test_send_sync() {
+        fn is_send_sync<T: Send + Sync>() {}
+        is_send_sync::<MemTable<FlexibleCompartor>>();
+    }
 
     #[test]
     fn test_new() {
         let mem = MemTable::new(1024, FixedLengthSuffixComparator::new(8));
     }
 
+    #[test]
+    fn test_delete_range() {
+        let mem = MemTable::new(1 << 20, FlexibleCompartor::new(8));
+        for i in 0..10 {
+            mem.put(format!("key_{}", i).as_bytes(), b"v").unwrap();
+        }
+        assert!(mem.delete_range(b"key_5", b"key_3").is_err());
+        mem.delete_range(b"key_3", b"key_6").unwrap();
+        assert!(mem.get(b"key_2").is_some());
+        assert!(mem.get(b"key_3").is_none());
+        assert!(mem.get(b"key_5").is_none());
+        assert!(mem.get(b"key_6").is_some());
+
+        // A put after the delete is visible and only cuts its own key out of the tombstone.
+        mem.put(b"key_4", b"v").unwrap();
+        mem.put(b"key_45", b"new").unwrap();
+        assert_eq!(mem.get(b"key_4"), Some(Bytes::from("v")));
+        assert_eq!(mem.get(b"key_45"), Some(Bytes::from("new")));
+        assert!(mem.get(b"key_3").is_none());
+        assert!(mem.get(b"key_5").is_none());
+        assert_eq!(mem.range_tombstones().len(), 3);
+    }
+
+    #[test]
+    fn test_refill_deleted_range() {
+        let mem = MemTable::new(1 << 22, FlexibleCompartor::new(8));
+        let keys: Vec<String> = (0..5000).map(|i| format!("key_{:05}", i)).collect();
+        for key in keys.iter().skip(1).step_by(2) {
+            mem.put(key.as_bytes(), b"old").unwrap();
+        }
+        mem.delete_range(b"key_", b"key_99999").unwrap();
+        mem.delete_range(b"key_01000", b"key_02000").unwrap();
+        assert_eq!(mem.range_tombstones().len(), 3);
+        let iter = mem.scan(Bound::Unbounded, Bound::Unbounded);
+        assert!(!iter.is_valid());
+
+        // Fill the other keys, each put cutting its key out of a fragment. Cutting the start key of
+        // a fragment does not add one.
+        for key in keys.iter().step_by(2) {
+            mem.put(key.as_bytes(), b"new").unwrap();
+        }
+        assert_eq!(mem.range_tombstones().len(), 2501);
+        for (i, key) in keys.iter().enumerate() {
+            let expected = (i % 2 == 0).then(|| Bytes::from("new"));
+            assert_eq!(mem.get(key.as_bytes()), expected);
+        }
+        let mut iter = mem.scan(Bound::Included(b"key_00999"), Bound::Excluded(b"key_02001"));
+        for key in keys[1000..2001].iter().step_by(2) {
+            assert_eq!(iter.key(), key.as_bytes());
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+        let mut iter = mem.scan_rev(Bound::Included(b"key_00999"), Bound::Excluded(b"key_02001"));
+        for key in keys[1000..2001].iter().step_by(2).rev() {
+            assert_eq!(iter.key(), key.as_bytes());
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    }
+
+    #[test]
+    fn test_put_racing_delete_range() {
+        let mem = MemTable::new(1 << 20, FlexibleCompartor::new(8));
+        mem.delete_range(b"key_1", b"key_5").unwrap();
+        // A delete_range that returns between the steps of a put is newer than the put.
+        let seq = mem.range_tombstones.read().unwrap().next_seq;
+        mem.rep.put(b"key_2", b"v").unwrap();
+        mem.delete_range(b"key_0", b"key_3").unwrap();
+        mem.cut_range_tombstones(b"key_2", seq);
+        assert!(mem.get(b"key_2").is_none());
+
+        mem.put(b"key_2", b"v").unwrap();
+        assert_eq!(mem.get(b"key_2"), Some(Bytes::from("v")));
+    }
+
+    #[test]
+    fn test_put_sorted_batch() {
+        let mem = MemTable::new(1 << 20, FlexibleCompartor::new(8));
+        mem.delete_range(b"key_010", b"key_020").unwrap();
+        let keys: Vec<String> = (0..100).map(|i| format!("key_{:03}", i)).collect();
+        let items: Vec<(&[u8], &[u8])> = keys.iter().map(|k| (k.as_bytes(), b"v".as_slice())).collect();
+        mem.put_sorted_batch(&items).unwrap();
+        for key in &keys {
+            assert_eq!(mem.get(key.as_bytes()), Some(Bytes::from("v")));
+        }
+        assert!(mem.put_sorted_batch(&[(b"key_100", b"v"), (b"key_050", b"other")]).is_err());
+        assert!(mem.get(b"key_100").is_some());
+
+        // The pairs after a failing one are not put, and must not cut the range tombstones.
+        let mem = MemTable::new(1 << 20, FlexibleCompartor::new(8));
+        mem.put(b"key_001", b"v").unwrap();
+        mem.put(b"key_050", b"old").unwrap();
+        mem.delete_range(b"key_040", b"key_060").unwrap();
+        assert!(mem.put_sorted_batch(&[(b"key_001", b"other"), (b"key_050", b"new")]).is_err());
+        assert!(mem.get(b"key_050").is_none());
+    }
+
+    #[test]
+    fn test_scan_rev() {
+        let mem = MemTable::new(1 << 20, FlexibleCompartor::new(8));
+        for i in 0..100 {
+            mem.put(format!("key_{:03}", i).as_bytes(), format!("{}", i).as_bytes()).unwrap();
+        }
+        mem.delete_range(b"key_040", b"key_060").unwrap();
+        let collect = |lower: Bound<&[u8]>, upper: Bound<&[u8]>| {
+            let mut iter = mem.scan_rev(lower, upper);
+            let mut values = vec![];
+            while iter.is_valid() {
+                values.push(String::from_utf8(iter.value().to_vec()).unwrap().parse::<usize>().unwrap());
+                iter.next().unwrap();
+            }
+            values
+        };
+
+        let all = collect(Bound::Unbounded, Bound::Unbounded);
+        assert_eq!(all, (0..100).rev().filter(|i| !(40..60).contains(i)).collect::<Vec<_>>());
+        let some = collect(Bound::Excluded(b"key_010"), Bound::Included(b"key_065"));
+        assert_eq!(some, (11..=65).rev().filter(|i| !(40..60).contains(i)).collect::<Vec<_>>());
+        let some = collect(Bound::Included(b"key_010"), Bound::Excluded(b"key_065"));
+        assert_eq!(some, (10..65).rev().filter(|i| !(40..60).contains(i)).collect::<Vec<_>>());
+        assert!(collect(Bound::Included(b"key_041"), Bound::Included(b"key_059")).is_empty());
+    }
+
+    #[test]
+    fn test_full_memtable() {
+        let mem = MemTable::new(4096, FlexibleCompartor::new(8));
+        let mut i = 0;
+        let err = loop {
+            if let Err(e) = mem.put(format!("key_{:04}", i).as_bytes(), b"v") {
+                break e;
+            }
+            i += 1;
+        };
+        assert_eq!(err.downcast_ref(), Some(&AllocError::ArenaFull));
+        assert!(mem.get(format!("key_{:04}", i).as_bytes()).is_none());
+        assert!(mem.get(format!("key_{:04}", i - 1).as_bytes()).is_some());
+
+        let mem = MemTable::new_chained(4096, 4, FlexibleCompartor::new(8));
+        for j in 0..i * 3 {
+            mem.put(format!("key_{:04}", j).as_bytes(), b"v").unwrap();
+        }
+        for j in 0..i * 3 {
+            assert!(mem.get(format!("key_{:04}", j).as_bytes()).is_some());
+        }
+
+        // An entry larger than a chunk fails even on an empty memtable, so rotating does not help.
+        let mem = MemTable::new_chained(4096, 4, FlexibleCompartor::new(8));
+        let err = mem.put(b"key", &[0; 4096]).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(AllocError::EntryTooLarge { max_size: 4096, .. })));
+        mem.put(b"key", &[0; 1024]).unwrap();
+    }
+
+    #[test]
+    #[ignore = "allocates 5 GiB, which fails where memory is not overcommitted"]
+    fn test_large_memtable() {
+        // Capacities past 4 GiB must not be truncated to 32 bits.
+        let mem = MemTable::new(5 << 30, FlexibleCompartor::new(8));
+        mem.put(b"key", b"v").unwrap();
+        assert_eq!(mem.get(b"key"), Some(Bytes::from("v")));
+    }
+
+    #[test]
+    #[should_panic(expected = "arena must not be larger than")]
+    fn test_too_large_memtable() {
+        MemTable::new(64 << 30, FlexibleCompartor::new(8));
+    }
+
+    #[test]
+    fn test_reps() {
+        let kinds = [
+            MemTableRepKind::SkipList,
+            MemTableRepKind::Vector,
+            MemTableRepKind::HashLinkList { prefix_len: 5 },
+        ];
+        for kind in kinds {
+            let mem = MemTable::with_rep(kind, 1 << 20, FlexibleCompartor::new(8));
+            for i in (0..100).rev() {
+                mem.put(format!("key_{:03}", i).as_bytes(), format!("{}", i).as_bytes()).unwrap();
+            }
+            mem.delete_range(b"key_040", b"key_060").unwrap();
+            assert_eq!(mem.get(b"key_007"), Some(Bytes::from("7")));
+            assert!(mem.get(b"key_045").is_none());
+            assert!(mem.get(b"key_100").is_none());
+            assert!(mem.mem_size() > 0);
+
+            let mut iter = mem.scan(Bound::Excluded(b"key_010"), Bound::Included(b"key_065"));
+            let mut values = vec![];
+            while iter.is_valid() {
+                values.push(String::from_utf8(iter.value().to_vec()).unwrap().parse::<usize>().unwrap());
+                iter.next().unwrap();
+            }
+            assert_eq!(values, (11..=65).filter(|i| !(40..60).contains(i)).collect::<Vec<_>>(), "{:?}", kind);
+
+            mem.freeze().unwrap();
+            let mut iter = mem.scan_rev(Bound::Included(b"key_010"), Bound::Excluded(b"key_065"));
+            let mut values = vec![];
+            while iter.is_valid() {
+                values.push(String::from_utf8(iter.value().to_vec()).unwrap().parse::<usize>().unwrap());
+                iter.next().unwrap();
+            }
+            assert_eq!(values, (10..65).rev().filter(|i| !(40..60).contains(i)).collect::<Vec<_>>(), "{:?}", kind);
+            assert_eq!(mem.get(b"key_099"), Some(Bytes::from("99")));
+        }
+    }
+
+    #[test]
+    fn test_vector_rep() {
+        let mem = MemTable::with_rep(MemTableRepKind::Vector, 0, FlexibleCompartor::new(8));
+        mem.put(b"key_2", b"first").unwrap();
+        mem.put(b"key_1", b"v").unwrap();
+        assert!(mem.put(b"key_2", b"second").is_err());
+        // Putting a key again with the same value is not a conflict.
+        mem.put(b"key_1", b"v").unwrap();
+
+        // A rejected put must not cut the range tombstones.
+        mem.put(b"key_4", b"old").unwrap();
+        mem.delete_range(b"key_0", b"key_9").unwrap();
+        assert!(mem.put(b"key_4", b"new").is_err());
+        assert!(mem.get(b"key_4").is_none());
+        mem.put(b"key_2", b"first").unwrap();
+
+        mem.freeze().unwrap();
+        assert!(mem.put(b"key_3", b"v").is_err());
+        assert_eq!(mem.get(b"key_2"), Some(Bytes::from("first")));
+
+        let mut iter = mem.scan(Bound::Unbounded, Bound::Unbounded);
+        let mut keys = vec![];
+        while iter.is_valid() {
+            keys.push(Bytes::copy_from_slice(iter.key()));
+            iter.next().unwrap();
+        }
+        assert_eq!(keys, vec![Bytes::from("key_2")]);
+    }
+
+    /// Orders keys ignoring ASCII case, so keys with different bytes can be equal.
+    #[derive(Clone, Copy)]
+    struct CaseInsensitiveComparator;
+
+    impl KeyComparator for CaseInsensitiveComparator {
+        fn compare_key(&self, lhs: &[u8], rhs: &[u8]) -> std::cmp::Ordering {
+            lhs.to_ascii_lowercase().cmp(&rhs.to_ascii_lowercase())
+        }
+
+        fn same_key(&self, lhs: &[u8], rhs: &[u8]) -> bool {
+            lhs.eq_ignore_ascii_case(rhs)
+        }
+
+        fn name(&self) -> String {
+            "CaseInsensitiveComparator".to_string()
+        }
+    }
+
+    #[test]
+    fn test_vector_rep_conflict_on_freeze() {
+        let mem = MemTable::with_rep(MemTableRepKind::Vector, 0, CaseInsensitiveComparator);
+        mem.put(b"key", b"first").unwrap();
+        mem.put(b"KEY", b"second").unwrap();
+        let err = mem.freeze().unwrap_err();
+        assert!(err.to_string().contains("KEY"), "{}", err);
+        assert_eq!(mem.get(b"Key"), Some(Bytes::from("first")));
+    }
+
     #[test]
     fn test_bytes()
     {
@@ -0,0 +1,380 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+
+use crate::skip_list::{IterRef, KeyComparator, Skiplist, Splice};
+
+/// Number of buckets of a `HashLinkListRep`.
+const NUM_BUCKETS: usize = 1024;
+/// Bookkeeping bytes counted for each entry of the reps that are not in an arena.
+const ENTRY_OVERHEAD: usize = 2 * std::mem::size_of::<Bytes>();
+
+/// Selects the data structure a memtable keeps its entries in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemTableRepKind {
+    /// A lock-free skiplist, good at everything.
+    SkipList,
+    /// An unsorted vector, sorted when the memtable is frozen. Puts are cheap, but reads before
+    /// the freeze sort or scan the whole vector, so it is meant for bulk loads.
+    Vector,
+    /// A hash table over the first `prefix_len` bytes of the keys, each bucket being a sorted
+    /// list. Point lookups only search one bucket, but scans sort all the entries.
+    HashLinkList { prefix_len: usize },
+}
+
+/// The data structure holding the entries of a memtable. The first put of a key wins, a later put
+/// of the key with another value is an error, reported by the put or, for a `VectorRep`, by
+/// `freeze`. A rep is shared by the threads writing to the memtable.
+pub trait MemTableRep<C: KeyComparator>: Send + Sync {
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()>;
+
+    /// Put key-value pairs sorted by key, stopping at the first failing one. Returns the number of
+    /// pairs put, and the error of the failing pair if any.
+    fn put_sorted_batch(&self, items: &[(&[u8], &[u8])]) -> (usize, Result<()>) {
+        for (i, (key, value)) in items.iter().enumerate() {
+            if let Err(e) = self.put(key, value) {
+                return (i, Err(e));
+            }
+        }
+        (items.len(), Ok(()))
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Bytes>;
+
+    /// Get an iterator over all the entries in key order. It is not positioned until it is seeked.
+    fn iter(&self) -> Box<dyn MemTableRepIter + '_>;
+
+    /// Called once the memtable stops taking writes.
+    fn freeze(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Get the memory used by the entries.
+    fn mem_size(&self) -> usize;
+
+    fn comparator(&self) -> &C;
+}
+
+/// A cursor over the entries of a `MemTableRep`.
+pub trait MemTableRepIter {
+    fn valid(&self) -> bool;
+    fn key(&self) -> &[u8];
+    fn value(&self) -> &[u8];
+    fn next(&mut self);
+    fn prev(&mut self);
+    /// Move to the first key at or after `target`.
+    fn seek(&mut self, target: &[u8]);
+    /// Move to the last key at or before `target`.
+    fn seek_for_prev(&mut self, target: &[u8]);
+    fn seek_to_first(&mut self);
+    fn seek_to_last(&mut self);
+}
+
+impl<C: KeyComparator + Send + Sync> MemTableRep<C> for Skiplist<C> {
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        match Skiplist::put(self, key, value)? {
+            None => Ok(()),
+            Some(_) => Err(anyhow!("put item error")),
+        }
+    }
+
+    fn put_sorted_batch(&self, items: &[(&[u8], &[u8])]) -> (usize, Result<()>) {
+        let mut splice = Splice::default();
+        for (i, (key, value)) in items.iter().enumerate() {
+            match self.put_with_hint(key, value, &mut splice) {
+                Ok(None) => {}
+                Ok(Some(_)) => return (i, Err(anyhow!("put item error"))),
+                Err(e) => return (i, Err(e.into())),
+            }
+        }
+        (items.len(), Ok(()))
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Bytes> {
+        Skiplist::get(self, key).map(Bytes::copy_from_slice)
+    }
+
+    fn iter(&self) -> Box<dyn MemTableRepIter + '_> {
+        Box::new(self.iter_ref())
+    }
+
+    fn mem_size(&self) -> usize {
+        Skiplist::mem_size(self)
+    }
+
+    fn comparator(&self) -> &C {
+        Skiplist::comparator(self)
+    }
+}
+
+impl<'a, C: KeyComparator> MemTableRepIter for IterRef<'a, C> {
+    fn valid(&self) -> bool {
+        IterRef::valid(self)
+    }
+
+    fn key(&self) -> &[u8] {
+        IterRef::key(self)
+    }
+
+    fn value(&self) -> &[u8] {
+        IterRef::value(self)
+    }
+
+    fn next(&mut self) {
+        IterRef::next(self)
+    }
+
+    fn prev(&mut self) {
+        IterRef::prev(self)
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        IterRef::seek(self, target)
+    }
+
+    fn seek_for_prev(&mut self, target: &[u8]) {
+        IterRef::seek_for_prev(self, target)
+    }
+
+    fn seek_to_first(&mut self) {
+        IterRef::seek_to_first(self)
+    }
+
+    fn seek_to_last(&mut self) {
+        IterRef::seek_to_last(self)
+    }
+}
+
+/// Sort entries by key, keeping the first of the entries with the same key. Returns the first key
+/// put again with another value, which is a conflict.
+fn sort_entries<C: KeyComparator>(c: &C, entries: &mut Vec<(Bytes, Bytes)>) -> Option<Bytes> {
+    // The sort is stable, so the entries of a key stay in the order they were put.
+    entries.sort_by(|a, b| c.compare_key(&a.0, &b.0));
+    let mut conflict = None;
+    entries.dedup_by(|later, first| {
+        let same = c.compare_key(&later.0, &first.0).is_eq();
+        if same && later.1 != first.1 && conflict.is_none() {
+            conflict = Some(later.0.clone());
+        }
+        same
+    });
+    conflict
+}
+
+/// Get the value of `key` from sorted entries, looked up like in the skiplist.
+fn seek_sorted<C: KeyComparator>(c: &C, entries: &[(Bytes, Bytes)], key: &[u8]) -> Option<Bytes> {
+    let (k, v) = entries.get(entries.partition_point(|(k, _)| c.compare_key(k, key).is_lt()))?;
+    c.same_key(k, key).then(|| v.clone())
+}
+
+/// Iterates over a sorted snapshot of entries.
+struct SortedIter<'a, C: KeyComparator> {
+    entries: Arc<Vec<(Bytes, Bytes)>>,
+    /// `entries.len()` when the iterator is not valid.
+    pos: usize,
+    c: &'a C,
+}
+
+impl<'a, C: KeyComparator> SortedIter<'a, C> {
+    fn new(entries: Arc<Vec<(Bytes, Bytes)>>, c: &'a C) -> Self {
+        let pos = entries.len();
+        Self { entries, pos, c }
+    }
+}
+
+impl<'a, C: KeyComparator> MemTableRepIter for SortedIter<'a, C> {
+    fn valid(&self) -> bool {
+        self.pos < self.entries.len()
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.entries[self.pos].0
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.entries[self.pos].1
+    }
+
+    fn next(&mut self) {
+        assert!(self.valid());
+        self.pos += 1;
+    }
+
+    fn prev(&mut self) {
+        assert!(self.valid());
+        self.pos = self.pos.checked_sub(1).unwrap_or(self.entries.len());
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        self.pos = self.entries.partition_point(|(k, _)| self.c.compare_key(k, target).is_lt());
+    }
+
+    fn seek_for_prev(&mut self, target: &[u8]) {
+        let end = self.entries.partition_point(|(k, _)| self.c.compare_key(k, target).is_le());
+        self.pos = end.checked_sub(1).unwrap_or(self.entries.len());
+    }
+
+    fn seek_to_first(&mut self) {
+        self.pos = 0;
+    }
+
+    fn seek_to_last(&mut self) {
+        self.pos = self.entries.len().saturating_sub(1);
+    }
+}
+
+/// Appends entries to a vector and sorts it when the memtable is frozen, see
+/// `MemTableRepKind::Vector`. Puts after the freeze are errors. A hash map of the values put so far
+/// rejects a later put of a key with another value without searching the vector.
+pub struct VectorRep<C: KeyComparator> {
+    entries: RwLock<VectorEntries>,
+    frozen: AtomicBool,
+    mem_size: AtomicUsize,
+    c: C,
+}
+
+#[derive(Default)]
+struct VectorEntries {
+    /// In put order until the rep is frozen, then sorted.
+    list: Arc<Vec<(Bytes, Bytes)>>,
+    /// The value of every key put, by the bytes of the key. Dropped by the freeze.
+    values: HashMap<Bytes, Bytes>,
+}
+
+impl<C: KeyComparator> VectorRep<C> {
+    pub fn new(c: C) -> Self {
+        Self {
+            entries: RwLock::new(VectorEntries::default()),
+            frozen: AtomicBool::new(false),
+            mem_size: AtomicUsize::new(0),
+            c,
+        }
+    }
+}
+
+impl<C: KeyComparator + Send + Sync> MemTableRep<C> for VectorRep<C> {
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut entries = self.entries.write().unwrap();
+        if self.frozen.load(Ordering::Acquire) {
+            return Err(anyhow!("memtable is frozen"));
+        }
+        match entries.values.get(key) {
+            Some(v) if v == value => return Ok(()),
+            Some(_) => return Err(anyhow!("put item error")),
+            None => {}
+        }
+        self.mem_size.fetch_add(key.len() + value.len() + ENTRY_OVERHEAD, Ordering::Relaxed);
+        let (key, value) = (Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
+        entries.values.insert(key.clone(), value.clone());
+        Arc::make_mut(&mut entries.list).push((key, value));
+        Ok(())
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Bytes> {
+        let entries = self.entries.read().unwrap();
+        if self.frozen.load(Ordering::Acquire) {
+            return seek_sorted(&self.c, &entries.list, key);
+        }
+        entries.list.iter().find(|(k, _)| self.c.same_key(k, key)).map(|(_, v)| v.clone())
+    }
+
+    fn iter(&self) -> Box<dyn MemTableRepIter + '_> {
+        let entries = self.entries.read().unwrap();
+        if self.frozen.load(Ordering::Acquire) {
+            return Box::new(SortedIter::new(entries.list.clone(), &self.c));
+        }
+        let mut snapshot = entries.list.to_vec();
+        // Conflicts the puts could not catch are reported by `freeze`.
+        sort_entries(&self.c, &mut snapshot);
+        Box::new(SortedIter::new(Arc::new(snapshot), &self.c))
+    }
+
+    fn freeze(&self) -> Result<()> {
+        let mut entries = self.entries.write().unwrap();
+        if self.frozen.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+        entries.values = HashMap::new();
+        // Puts only catch keys with the same bytes. A comparator may also order other keys as equal.
+        match sort_entries(&self.c, Arc::make_mut(&mut entries.list)) {
+            Some(key) => Err(anyhow!("put item error: conflicting values for key {:?}", key)),
+            None => Ok(()),
+        }
+    }
+
+    fn mem_size(&self) -> usize {
+        self.mem_size.load(Ordering::Relaxed)
+    }
+
+    fn comparator(&self) -> &C {
+        &self.c
+    }
+}
+
+/// Hashes the prefix of a key to a bucket holding a sorted list of entries, see
+/// `MemTableRepKind::HashLinkList`.
+pub struct HashLinkListRep<C: KeyComparator> {
+    buckets: Vec<RwLock<Vec<(Bytes, Bytes)>>>,
+    prefix_len: usize,
+    mem_size: AtomicUsize,
+    c: C,
+}
+
+impl<C: KeyComparator> HashLinkListRep<C> {
+    pub fn new(prefix_len: usize, c: C) -> Self {
+        Self {
+            buckets: (0..NUM_BUCKETS).map(|_| RwLock::new(Vec::new())).collect(),
+            prefix_len,
+            mem_size: AtomicUsize::new(0),
+            c,
+        }
+    }
+
+    fn bucket(&self, key: &[u8]) -> &RwLock<Vec<(Bytes, Bytes)>> {
+        let mut hasher = DefaultHasher::new();
+        key[..key.len().min(self.prefix_len)].hash(&mut hasher);
+        &self.buckets[hasher.finish() as usize % NUM_BUCKETS]
+    }
+}
+
+impl<C: KeyComparator + Send + Sync> MemTableRep<C> for HashLinkListRep<C> {
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut bucket = self.bucket(key).write().unwrap();
+        match bucket.binary_search_by(|(k, _)| self.c.compare_key(k, key)) {
+            Ok(idx) if bucket[idx].1 == value => Ok(()),
+            Ok(_) => Err(anyhow!("put item error")),
+            Err(idx) => {
+                bucket.insert(idx, (Bytes::copy_from_slice(key), Bytes::copy_from_slice(value)));
+                self.mem_size.fetch_add(key.len() + value.len() + ENTRY_OVERHEAD, Ordering::Relaxed);
+                Ok(())
+            }
+        }
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Bytes> {
+        seek_sorted(&self.c, &self.bucket(key).read().unwrap(), key)
+    }
+
+    fn iter(&self) -> Box<dyn MemTableRepIter + '_> {
+        let mut entries = vec![];
+        for bucket in &self.buckets {
+            entries.extend(bucket.read().unwrap().iter().cloned());
+        }
+        // A key always hashes to the same bucket, which rejects conflicting puts.
+        sort_entries(&self.c, &mut entries);
+        Box::new(SortedIter::new(Arc::new(entries), &self.c))
+    }
+
+    fn mem_size(&self) -> usize {
+        self.mem_size.load(Ordering::Relaxed)
+    }
+
+    fn comparator(&self) -> &C {
+        &self.c
+    }
+}
@@ -0,0 +1,195 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use bytes::Bytes;
+
+use crate::range_tombstone::RangeTombstone;
+use crate::skip_list::KeyComparator;
+
+/// Range tombstones of a memtable, kept as non-overlapping fragments sorted by start key, so that
+/// finding the fragment covering a key is O(log n). Each fragment has the sequence number of the
+/// newest tombstone covering it. A put only cuts its key out of the fragments added before it
+/// started, so a `delete_range` that returns while the put is running is never undone by it.
+pub(super) struct RangeTombstones<C: KeyComparator> {
+    pub(super) next_seq: u64,
+    fragments: BTreeMap<StartKey<C>, Fragment>,
+    c: C,
+}
+
+/// Start key of a fragment, ordered by the comparator of the memtable. No two fragments have the
+/// same start key, since `[k, ..)` and `(k, ..)` would overlap.
+#[derive(Clone)]
+struct StartKey<C> {
+    key: Bytes,
+    c: C,
+}
+
+impl<C: KeyComparator> PartialEq for StartKey<C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<C: KeyComparator> Eq for StartKey<C> {}
+
+impl<C: KeyComparator> PartialOrd for StartKey<C> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<C: KeyComparator> Ord for StartKey<C> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.c.compare_key(&self.key, &other.key)
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct Fragment {
+    pub(super) tombstone: RangeTombstone,
+    seq: u64,
+}
+
+impl<C: KeyComparator + Clone> RangeTombstones<C> {
+    pub(super) fn new(c: C) -> Self {
+        Self {
+            next_seq: 0,
+            fragments: BTreeMap::new(),
+            c,
+        }
+    }
+
+    fn start_key(&self, key: &[u8]) -> StartKey<C> {
+        StartKey {
+            key: Bytes::copy_from_slice(key),
+            c: self.c.clone(),
+        }
+    }
+
+    /// Get the fragment covering `key`.
+    pub(super) fn find(&self, key: &[u8]) -> Option<&Fragment> {
+        let (_, fragment) = self.fragments.range(..=self.start_key(key)).next_back()?;
+        fragment.tombstone.covers(&self.c, key).then_some(fragment)
+    }
+
+    pub(super) fn covers(&self, key: &[u8]) -> bool {
+        self.find(key).is_some()
+    }
+
+    /// Returns whether a put that started at `seq` must cut `key` out of the fragments.
+    pub(super) fn needs_cut(&self, key: &[u8], seq: u64) -> bool {
+        self.find(key).is_some_and(|fragment| fragment.seq < seq)
+    }
+
+    /// Delete `[start, end)` with the next sequence number. The range must have been validated.
+    pub(super) fn add(&mut self, start: &[u8], end: &[u8]) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let (start, end) = (self.start_key(start), self.start_key(end));
+        let mut overlapping: Vec<StartKey<C>> = self
+            .fragments
+            .range(start.clone()..end.clone())
+            .map(|(k, _)| k.clone())
+            .collect();
+        if let Some((key, fragment)) = self.fragments.range(..start.clone()).next_back() {
+            if self.c.compare_key(&fragment.tombstone.end, &start.key) == Ordering::Greater {
+                overlapping.push(key.clone());
+            }
+        }
+        // The new tombstone is the newest one, so it replaces the parts of the fragments it covers.
+        for key in overlapping {
+            let fragment = self.fragments.remove(&key).unwrap();
+            let left = RangeTombstone {
+                start: fragment.tombstone.start.clone(),
+                end: start.key.clone(),
+            };
+            let right = RangeTombstone {
+                start: Bound::Included(end.key.clone()),
+                end: fragment.tombstone.end.clone(),
+            };
+            for tombstone in [left, right] {
+                if tombstone.validate(&self.c).is_ok() {
+                    self.insert(Fragment {
+                        tombstone,
+                        seq: fragment.seq,
+                    });
+                }
+            }
+        }
+        let tombstone = RangeTombstone {
+            start: Bound::Included(start.key),
+            end: end.key,
+        };
+        self.insert(Fragment { tombstone, seq });
+    }
+
+    /// Cut `key` out of the fragment covering it if it was added before `seq`.
+    pub(super) fn cut(&mut self, key: &[u8], seq: u64) {
+        if !self.needs_cut(key, seq) {
+            return;
+        }
+        let (start, _) = self
+            .fragments
+            .range(..=self.start_key(key))
+            .next_back()
+            .unwrap();
+        let start = start.clone();
+        let fragment = self.fragments.remove(&start).unwrap();
+        let (left, right) = fragment.tombstone.split_at(&self.c, key);
+        for tombstone in left.into_iter().chain(right) {
+            self.insert(Fragment {
+                tombstone,
+                seq: fragment.seq,
+            });
+        }
+    }
+
+    fn insert(&mut self, fragment: Fragment) {
+        let start = match &fragment.tombstone.start {
+            Bound::Included(start) | Bound::Excluded(start) => self.start_key(start),
+            Bound::Unbounded => unreachable!("range tombstone without a start key"),
+        };
+        self.fragments.insert(start, fragment);
+    }
+
+    pub(super) fn tombstones(&self) -> Vec<RangeTombstone> {
+        self.fragments
+            .values()
+            .map(|fragment| fragment.tombstone.clone())
+            .collect()
+    }
+
+    /// Copy the fragments that may cover a key between `lower` and `upper`, e.g. for an iterator
+    /// over that range.
+    pub(super) fn overlapping(&self, lower: &Bound<Bytes>, upper: &Bound<Bytes>) -> Self {
+        let mut snapshot = Self::new(self.c.clone());
+        snapshot.next_seq = self.next_seq;
+        let from = match lower {
+            Bound::Included(key) | Bound::Excluded(key) => {
+                let key = self.start_key(key);
+                // The fragment before the lower bound may still cover it.
+                match self.fragments.range(..=key.clone()).next_back() {
+                    Some((start, _)) => Bound::Included(start.clone()),
+                    None => Bound::Included(key),
+                }
+            }
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let to = match upper {
+            Bound::Included(key) | Bound::Excluded(key) => Bound::Included(self.start_key(key)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        if let (Bound::Included(from), Bound::Included(to)) = (&from, &to) {
+            if from > to {
+                return snapshot;
+            }
+        }
+        snapshot.fragments = self
+            .fragments
+            .range((from, to))
+            .map(|(k, f)| (k.clone(), f.clone()))
+            .collect();
+        snapshot
+    }
+}
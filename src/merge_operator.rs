@@ -0,0 +1,154 @@
+use anyhow::{anyhow, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+/// Combines merge operands into a value, so that read-modify-write updates such as counters or
+/// appends can be written blindly and resolved later. There is no write or read path calling it
+/// yet; this is only the operator interface and its built-in operators.
+pub trait MergeOperator: Send + Sync {
+    /// The name of the operator.
+    fn name(&self) -> &str;
+
+    /// Apply `operands`, oldest first, on top of `existing`, the value the key had before the first
+    /// operand, if any.
+    fn full_merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Result<Bytes>;
+
+    /// Combine two adjacent operands, `left` being the older one, without knowing the base value.
+    /// Returns `None` if they cannot be combined, in which case both are kept.
+    fn partial_merge(&self, _key: &[u8], _left: &[u8], _right: &[u8]) -> Option<Bytes> {
+        None
+    }
+}
+
+/// Collapse a run of operands, oldest first, with `partial_merge` as far as the operator allows.
+/// This is what a compaction would do when the base value of the key is not in its input.
+pub fn collapse_operands(op: &dyn MergeOperator, key: &[u8], operands: &[&[u8]]) -> Vec<Bytes> {
+    let mut collapsed: Vec<Bytes> = Vec::with_capacity(operands.len());
+    for operand in operands {
+        let merged = collapsed.last().and_then(|last| op.partial_merge(key, last, operand));
+        match merged {
+            Some(merged) => *collapsed.last_mut().unwrap() = merged,
+            None => collapsed.push(Bytes::copy_from_slice(operand)),
+        }
+    }
+    collapsed
+}
+
+/// Adds operands to the value, both being encoded as big-endian `u64`. A missing value counts as 0
+/// and the sum wraps around on overflow.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct U64AddOperator;
+
+impl U64AddOperator {
+    fn decode(key: &[u8], mut raw: &[u8]) -> Result<u64> {
+        if raw.len() != 8 {
+            return Err(anyhow!("u64 add operand of key {:?} has {} bytes", Bytes::copy_from_slice(key), raw.len()));
+        }
+        Ok(raw.get_u64())
+    }
+
+    fn encode(n: u64) -> Bytes {
+        Bytes::copy_from_slice(&n.to_be_bytes())
+    }
+}
+
+impl MergeOperator for U64AddOperator {
+    fn name(&self) -> &str {
+        "u64_add"
+    }
+
+    fn full_merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Result<Bytes> {
+        let mut sum = existing.map_or(Ok(0), |v| Self::decode(key, v))?;
+        for operand in operands {
+            sum = sum.wrapping_add(Self::decode(key, operand)?);
+        }
+        Ok(Self::encode(sum))
+    }
+
+    fn partial_merge(&self, key: &[u8], left: &[u8], right: &[u8]) -> Option<Bytes> {
+        let (left, right) = (Self::decode(key, left).ok()?, Self::decode(key, right).ok()?);
+        Some(Self::encode(left.wrapping_add(right)))
+    }
+}
+
+/// Appends operands to the value, separated by `delimiter` if one is set.
+#[derive(Default, Debug, Clone)]
+pub struct BytesAppendOperator {
+    delimiter: Bytes,
+}
+
+impl BytesAppendOperator {
+    pub fn new(delimiter: &[u8]) -> Self {
+        Self { delimiter: Bytes::copy_from_slice(delimiter) }
+    }
+
+    /// Join `parts` with the delimiter. An empty part is still delimited, so that merging operands
+    /// one by one or partially merged gives the same result.
+    fn join<'a>(&self, parts: impl IntoIterator<Item = &'a [u8]>) -> Bytes {
+        let mut buf = BytesMut::new();
+        for (i, part) in parts.into_iter().enumerate() {
+            if i > 0 {
+                buf.put_slice(&self.delimiter);
+            }
+            buf.put_slice(part);
+        }
+        buf.freeze()
+    }
+}
+
+impl MergeOperator for BytesAppendOperator {
+    fn name(&self) -> &str {
+        "bytes_append"
+    }
+
+    fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Result<Bytes> {
+        Ok(self.join(existing.into_iter().chain(operands.iter().copied())))
+    }
+
+    fn partial_merge(&self, _key: &[u8], left: &[u8], right: &[u8]) -> Option<Bytes> {
+        Some(self.join([left, right]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn n(n: u64) -> Vec<u8> {
+        n.to_be_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_u64_add() {
+        let op = U64AddOperator;
+        assert_eq!(op.full_merge(b"k", None, &[&n(1), &n(2)]).unwrap(), n(3));
+        assert_eq!(op.full_merge(b"k", Some(&n(10)), &[&n(5)]).unwrap(), n(15));
+        assert_eq!(op.full_merge(b"k", Some(&n(u64::MAX)), &[&n(2)]).unwrap(), n(1));
+        assert!(op.full_merge(b"k", Some(b"abc"), &[&n(1)]).is_err());
+        assert!(op.partial_merge(b"k", b"abc", &n(1)).is_none());
+
+        let collapsed = collapse_operands(&op, b"k", &[&n(1), &n(2), &n(3)]);
+        assert_eq!(collapsed, vec![Bytes::from(n(6))]);
+    }
+
+    #[test]
+    fn test_bytes_append() {
+        let op = BytesAppendOperator::new(b",");
+        assert_eq!(op.full_merge(b"k", None, &[b"a", b"b"]).unwrap(), "a,b");
+        assert_eq!(op.full_merge(b"k", Some(b"x"), &[b"a"]).unwrap(), "x,a");
+        assert_eq!(op.full_merge(b"k", Some(b"x"), &[]).unwrap(), "x");
+
+        let collapsed = collapse_operands(&op, b"k", &[b"a", b"b", b"c"]);
+        assert_eq!(collapsed, vec![Bytes::from("a,b,c")]);
+        // Partial merges of operands must give the same result as merging them one by one.
+        assert_eq!(op.full_merge(b"k", Some(b"x"), &[&collapsed[0]]).unwrap(), "x,a,b,c");
+
+        // Empty values and operands are delimited like any other.
+        assert_eq!(op.full_merge(b"k", None, &[b"", b"a"]).unwrap(), ",a");
+        assert_eq!(op.full_merge(b"k", Some(b""), &[b"a"]).unwrap(), ",a");
+        let collapsed = collapse_operands(&op, b"k", &[b"", b"a"]);
+        assert_eq!(op.full_merge(b"k", Some(b"x"), &[&collapsed[0]]).unwrap(), "x,,a");
+        assert_eq!(op.full_merge(b"k", Some(b"x"), &[b"", b"a"]).unwrap(), "x,,a");
+    }
+}
@@ -0,0 +1,102 @@
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+use anyhow::{anyhow, Result};
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::block::block_builder::BlockBuilder;
+use crate::block::Block;
+use crate::skip_list::KeyComparator;
+
+/// Deletes every key in `[start, end)`, or in `(start, end)` if `start` is excluded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeTombstone {
+    /// Start of the deleted range, never `Unbounded`.
+    pub start: Bound<Bytes>,
+    /// Exclusive end of the deleted range.
+    pub end: Bytes,
+}
+
+impl RangeTombstone {
+    /// Create a tombstone deleting `[start, end)`.
+    pub fn new(start: &[u8], end: &[u8]) -> Self {
+        Self {
+            start: Bound::Included(Bytes::copy_from_slice(start)),
+            end: Bytes::copy_from_slice(end),
+        }
+    }
+
+    /// Check that the range is not empty and its keys can be stored in a block. The end key is
+    /// stored after a flag byte in a value whose length is a u16, so it is at most `u16::MAX - 1`
+    /// bytes long.
+    pub fn validate<C: KeyComparator>(&self, c: &C) -> Result<()> {
+        let start = match &self.start {
+            Bound::Included(start) | Bound::Excluded(start) => start,
+            Bound::Unbounded => return Err(anyhow!("range tombstone must have a start key")),
+        };
+        if start.is_empty() || start.len() > u16::MAX as usize || self.end.len() > u16::MAX as usize - 1 {
+            return Err(anyhow!("bad range tombstone keys {:?}..{:?}", start, self.end));
+        }
+        if c.compare_key(start, &self.end) != Ordering::Less {
+            return Err(anyhow!("empty range tombstone {:?}..{:?}", start, self.end));
+        }
+        Ok(())
+    }
+
+    /// Returns whether `key` is in the deleted range.
+    pub fn covers<C: KeyComparator>(&self, c: &C, key: &[u8]) -> bool {
+        let after_start = match &self.start {
+            Bound::Included(start) => c.compare_key(key, start) != Ordering::Less,
+            Bound::Excluded(start) => c.compare_key(key, start) == Ordering::Greater,
+            Bound::Unbounded => true,
+        };
+        after_start && c.compare_key(key, &self.end) == Ordering::Less
+    }
+
+    /// Cut `key` out of the deleted range, returning the non-empty parts before and after it.
+    pub fn split_at<C: KeyComparator>(&self, c: &C, key: &[u8]) -> (Option<Self>, Option<Self>) {
+        let key = Bytes::copy_from_slice(key);
+        let left = Self { start: self.start.clone(), end: key.clone() };
+        let right = Self { start: Bound::Excluded(key), end: self.end.clone() };
+        (left.validate(c).ok().map(|_| left), right.validate(c).ok().map(|_| right))
+    }
+
+    /// Encode tombstones as a range deletion block. The key of each entry is the start key and the
+    /// value is a flag byte (1 if the start is excluded) followed by the end key.
+    pub fn encode_block(tombstones: &[RangeTombstone]) -> Bytes {
+        let mut builder = BlockBuilder::new_unbounded();
+        for tombstone in tombstones {
+            let (start, excluded) = match &tombstone.start {
+                Bound::Included(start) => (start, 0),
+                Bound::Excluded(start) => (start, 1),
+                Bound::Unbounded => unreachable!("range tombstone without a start key"),
+            };
+            let mut value = Vec::with_capacity(1 + tombstone.end.len());
+            value.put_u8(excluded);
+            value.put_slice(&tombstone.end);
+            let ok = builder.add(start, &value);
+            assert!(ok);
+        }
+        builder.build().encode_wide()
+    }
+
+    /// Decode tombstones from a range deletion block.
+    pub fn decode_block(data: &[u8]) -> Result<Vec<RangeTombstone>> {
+        let block = Block::decode_wide(data);
+        let mut tombstones = Vec::with_capacity(block.num_of_entries());
+        for idx in 0..block.num_of_entries() {
+            let start = Bytes::copy_from_slice(block.key_at(idx));
+            let mut value = block.value_at(idx);
+            if !value.has_remaining() {
+                return Err(anyhow!("corrupted range tombstone at {:?}", start));
+            }
+            let start = match value.get_u8() {
+                0 => Bound::Included(start),
+                1 => Bound::Excluded(start),
+                flag => return Err(anyhow!("bad range tombstone flag {}", flag)),
+            };
+            tombstones.push(RangeTombstone { start, end: Bytes::copy_from_slice(value) });
+        }
+        Ok(tombstones)
+    }
+}
@@ -1,29 +1,74 @@
 use std::{
-    ptr,
-    sync::{
-        Arc,
-        atomic::{AtomicU32, Ordering},
-    },
+    alloc::{self, Layout},
+    fmt, ptr,
+    sync::Arc,
 };
-use std::mem;
 
 use super::Allocator;
+use super::sync::{AtomicPtr, AtomicUsize, Mutex, Ordering};
+
+/// Offsets count units of `OFFSET_UNIT` bytes, so a `u32` offset addresses 32 GiB.
+const OFFSET_SHIFT: u32 = 3;
+const OFFSET_UNIT: usize = 1 << OFFSET_SHIFT;
+/// The largest arena whose offsets fit in a `u32`.
+const MAX_ARENA_SIZE: usize = (u32::MAX as usize + 1) << OFFSET_SHIFT;
+
+/// Returned when an arena cannot make an allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocError {
+    /// The arena has no room left. The memtable owning it should be frozen and replaced by a new
+    /// one.
+    ArenaFull,
+    /// The allocation is larger than a chunk, so it does not fit even in an empty arena and a new
+    /// memtable would not help.
+    EntryTooLarge { size: usize, max_size: usize },
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllocError::ArenaFull => write!(f, "arena is full"),
+            AllocError::EntryTooLarge { size, max_size } => {
+                write!(f, "entry too large: {} bytes, at most {} fit in an arena chunk", size, max_size)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AllocError {}
 
 #[derive(Debug)]
 struct InnerArena {
-    len: AtomicU32,
-    cap: usize,
-    pub ptr: *mut u8,
+    /// End of the allocated bytes, counted over all chunks as if they were contiguous.
+    len: AtomicUsize,
+    chunk_size: usize,
+    /// Chunks are allocated on demand, `null` until then.
+    chunks: Box<[AtomicPtr<u8>]>,
+    /// Serializes the allocation of new chunks.
+    grow: Mutex<()>,
+}
+
+impl InnerArena {
+    fn chunk_layout(&self) -> Layout {
+        Layout::from_size_align(self.chunk_size, OFFSET_UNIT).unwrap()
+    }
 }
 
 impl Drop for InnerArena {
     fn drop(&mut self) {
-        unsafe {
-            Vec::from_raw_parts(self.ptr as *mut u8, 0, self.cap);
+        let layout = self.chunk_layout();
+        for chunk in self.chunks.iter() {
+            let ptr = chunk.load(Ordering::Acquire);
+            if !ptr.is_null() {
+                unsafe { alloc::dealloc(ptr, layout) };
+            }
         }
     }
 }
 
+/// A bump allocator. A fixed arena is a single buffer, a chained arena grows by adding chunks until
+/// it reaches its maximum number of chunks. Allocations never span two chunks, and the offsets they
+/// return stay valid for the lifetime of the arena.
 #[derive(Debug)]
 pub struct Arena {
     inner: Arc<InnerArena>,
@@ -32,75 +77,126 @@ pub struct Arena {
 impl Arena {
     #[inline]
     pub fn with_capacity(cap: usize) -> Self {
-        let mut v: Vec<u64> = Vec::with_capacity(cap >> 3);
-        let ptr = v.as_mut_ptr() as *mut u8;
-        mem::forget(v);
+        let arena = Self::new(cap, 1);
+        arena.chunk(0);
+        arena
+    }
+
+    /// Create an arena that allocates chunks of `chunk_size` bytes as needed, up to `max_chunks`.
+    /// An allocation larger than a chunk fails with `EntryTooLarge`.
+    pub fn chained(chunk_size: usize, max_chunks: usize) -> Self {
+        Self::new(chunk_size, max_chunks)
+    }
+
+    fn new(chunk_size: usize, num_chunks: usize) -> Self {
+        let chunk_size = chunk_size >> OFFSET_SHIFT << OFFSET_SHIFT;
+        assert!(chunk_size > 0 && num_chunks > 0, "arena must not be empty");
+        assert!(
+            chunk_size.checked_mul(num_chunks).is_some_and(|size| size <= MAX_ARENA_SIZE),
+            "arena must not be larger than {} bytes",
+            MAX_ARENA_SIZE
+        );
         Self {
             inner: Arc::new(InnerArena {
-                len: AtomicU32::new(1),
-                cap: cap >> 3 << 3,
-                ptr,
+                // Offset 0 is the null offset.
+                len: AtomicUsize::new(OFFSET_UNIT),
+                chunk_size,
+                chunks: (0..num_chunks).map(|_| AtomicPtr::new(ptr::null_mut())).collect(),
+                grow: Mutex::new(()),
             }),
         }
     }
 
+    /// Get the chunk at `idx`, allocating it if it does not exist yet.
+    fn chunk(&self, idx: usize) -> *mut u8 {
+        let chunk = &self.inner.chunks[idx];
+        let ptr = chunk.load(Ordering::Acquire);
+        if !ptr.is_null() {
+            return ptr;
+        }
+        let _guard = self.inner.grow.lock().unwrap();
+        let ptr = chunk.load(Ordering::Acquire);
+        if !ptr.is_null() {
+            return ptr;
+        }
+        let layout = self.inner.chunk_layout();
+        let ptr = unsafe { alloc::alloc(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        chunk.store(ptr, Ordering::Release);
+        ptr
+    }
+
     pub unsafe fn get_mut<T>(&self, off: u32) -> *mut T {
         if off == 0 {
             return ptr::null_mut();
         }
-        self.inner.ptr.add(off as usize) as _
+        let pos = (off as usize) << OFFSET_SHIFT;
+        debug_assert!(pos < self.len(), "offset {} is not allocated", off);
+        let chunk = self.inner.chunks[pos / self.inner.chunk_size].load(Ordering::Acquire);
+        chunk.add(pos % self.inner.chunk_size) as _
     }
+}
 
-    pub fn offset<T>(&self, ptr: *mut T) -> u32 {
-        let ptr_addr = ptr as usize;
-        let addr = self.inner.ptr as usize;
-        if ptr_addr > addr + self.inner.cap || ptr_addr < addr {
-            return 0;
+impl Allocator for Arena {
+    fn alloc(&self, align: usize, size: usize) -> Result<u32, AllocError> {
+        assert_eq!(align & (align - 1), 0, "align must be power of 2");
+        assert!(align <= OFFSET_UNIT, "align must not be larger than {}", OFFSET_UNIT);
+        let chunk_size = self.inner.chunk_size;
+        // Every allocation starts at an offset unit, which satisfies `align`.
+        let size = (size + OFFSET_UNIT - 1) & !(OFFSET_UNIT - 1);
+        if size > chunk_size {
+            return Err(AllocError::EntryTooLarge { size, max_size: chunk_size });
+        }
+        // `len` only hands out disjoint ranges, the chunks are published by `chunk`.
+        let mut len = self.inner.len.load(Ordering::Relaxed);
+        loop {
+            let idx = len / chunk_size;
+            if idx >= self.inner.chunks.len() {
+                return Err(AllocError::ArenaFull);
+            }
+            // Skip the tail of the chunk if the allocation does not fit in it.
+            let start = if len + size > (idx + 1) * chunk_size { (idx + 1) * chunk_size } else { len };
+            if start / chunk_size >= self.inner.chunks.len() {
+                return Err(AllocError::ArenaFull);
+            }
+            match self.inner.len.compare_exchange_weak(len, start + size, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => {
+                    self.chunk(start / chunk_size);
+                    return Ok((start >> OFFSET_SHIFT) as u32);
+                }
+                Err(cur) => len = cur,
+            }
         }
-        (ptr_addr - addr) as u32
     }
-}
 
-impl Allocator for Arena {
-    fn alloc(&self, align: usize, size: usize) -> u32 {
-        assert_eq!(align & align - 1, 0, "align must be power of 2");
-        let align_mask = align - 1;
-        // Leave enough padding for align.
-        let size = size + align_mask;
-        // return 增加之前的地址
-        let offset = self.inner.len.fetch_add(size as u32, Ordering::SeqCst);
-        // (offset + align_mask) / align * align.
-        let ptr_offset = (offset as usize + align_mask) & !align_mask;
-        assert!(ptr_offset < self.inner.cap);
-        return ptr_offset as u32;
-    }
-
-    fn len(&self) -> u32 {
+    fn len(&self) -> usize {
         self.inner.len.load(Ordering::Acquire)
     }
 
     fn capacity(&self) -> usize {
-        self.inner.cap
+        self.inner.chunk_size * self.inner.chunks.len()
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::skip_list::{Allocator, Arena};
+    use std::ptr;
+
+    use crate::skip_list::{AllocError, Allocator, Arena};
 
     #[test]
     fn test() {
         let arena = super::Arena::with_capacity(1024);
         let align = 8;
         let size = 8;
-        println!("{}", arena.inner.ptr as usize);
-        let r = arena.alloc(align, size);
+        let r = arena.alloc(align, size).unwrap();
         println!("{}, {}", r, arena.len());
-        let r = arena.alloc(align, size);
+        let r = arena.alloc(align, size).unwrap();
         println!("{}, {}", r, arena.len());
-        let r = arena.alloc(align, size);
+        let r = arena.alloc(align, size).unwrap();
         println!("{}, {}", r, arena.len());
-        println!("{}", std::mem::align_of::<Vec<u64>>());
         let n: usize = 10;
         println!("{}", !n);
         let n: usize = 8;
@@ -116,4 +212,44 @@ mod test {
         }
         println!("1");
     }
+
+    #[test]
+    fn test_alignment() {
+        let arena = Arena::chained(256, 4);
+        for (i, align) in [1, 2, 4, 8].iter().cycle().take(64).enumerate() {
+            let off = arena.alloc(*align, i % 13 + 1).unwrap();
+            let ptr = unsafe { arena.get_mut::<u8>(off) };
+            assert_eq!(ptr as usize % align, 0);
+            unsafe { ptr::write_bytes(ptr, i as u8, i % 13 + 1) };
+        }
+        assert!(arena.len() <= arena.capacity());
+    }
+
+    #[test]
+    fn test_arena_full() {
+        let arena = Arena::with_capacity(64);
+        for _ in 0..7 {
+            arena.alloc(8, 8).unwrap();
+        }
+        assert_eq!(arena.alloc(8, 8), Err(AllocError::ArenaFull));
+        assert_eq!(arena.alloc(1, 1), Err(AllocError::ArenaFull));
+        assert_eq!(arena.len(), 64);
+    }
+
+    #[test]
+    fn test_chained_arena() {
+        let arena = Arena::chained(64, 3);
+        assert_eq!(arena.capacity(), 192);
+        assert_eq!(arena.alloc(8, 65), Err(AllocError::EntryTooLarge { size: 72, max_size: 64 }));
+        let mut offsets = vec![];
+        while let Ok(off) = arena.alloc(8, 24) {
+            unsafe { *arena.get_mut::<u64>(off) = off as u64 };
+            offsets.push(off);
+        }
+        // Allocations never span two chunks.
+        assert_eq!(offsets, vec![1, 4, 8, 11, 16, 19]);
+        for off in offsets {
+            assert_eq!(unsafe { *arena.get_mut::<u64>(off) }, off as u64);
+        }
+    }
 }
@@ -6,6 +6,66 @@ use bytes::Bytes;
 pub trait KeyComparator {
     fn compare_key(&self, lhs: &[u8], rhs: &[u8]) -> Ordering;
     fn same_key(&self, lhs: &[u8], rhs: &[u8]) -> bool;
+
+    /// Name of the ordering, persisted in every SsTable written with it. Two comparators must only
+    /// share a name if they order keys the same way.
+    fn name(&self) -> String;
+
+    /// Returns a short key `k` with `start <= k < limit`, assuming `start < limit`. Used for the
+    /// index keys of SsTables. The default does not shorten `start`.
+    fn find_shortest_separator(&self, start: &[u8], _limit: &[u8]) -> Vec<u8> {
+        start.to_vec()
+    }
+
+    /// Returns a short key `k` with `key <= k`. The default does not shorten `key`.
+    fn find_short_successor(&self, key: &[u8]) -> Vec<u8> {
+        key.to_vec()
+    }
+}
+
+/// Orders keys lexicographically by their bytes.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct BytewiseComparator;
+
+impl KeyComparator for BytewiseComparator {
+    #[inline]
+    fn compare_key(&self, lhs: &[u8], rhs: &[u8]) -> Ordering {
+        lhs.cmp(rhs)
+    }
+
+    #[inline]
+    fn same_key(&self, lhs: &[u8], rhs: &[u8]) -> bool {
+        lhs == rhs
+    }
+
+    fn name(&self) -> String {
+        "BytewiseComparator".to_string()
+    }
+
+    fn find_shortest_separator(&self, start: &[u8], limit: &[u8]) -> Vec<u8> {
+        let min_len = start.len().min(limit.len());
+        let diff = start.iter().zip(limit).take_while(|(a, b)| a == b).count();
+        if diff < min_len {
+            let byte = start[diff];
+            if byte < u8::MAX && byte + 1 < limit[diff] {
+                let mut separator = start[..=diff].to_vec();
+                separator[diff] += 1;
+                return separator;
+            }
+        }
+        start.to_vec()
+    }
+
+    fn find_short_successor(&self, key: &[u8]) -> Vec<u8> {
+        match key.iter().position(|b| *b != u8::MAX) {
+            Some(idx) => {
+                let mut successor = key[..=idx].to_vec();
+                successor[idx] += 1;
+                successor
+            }
+            None => key.to_vec(),
+        }
+    }
 }
 
 #[derive(Default, Debug, Clone, Copy)]
@@ -40,6 +100,10 @@ impl KeyComparator for FlexibleCompartor {
     fn same_key(&self, lhs: &[u8], rhs: &[u8]) -> bool {
         lhs.cmp(rhs) == Ordering::Equal
     }
+
+    fn name(&self) -> String {
+        format!("FlexibleCompartor({})", self.len)
+    }
 }
 
 
@@ -81,4 +145,8 @@ impl KeyComparator for FixedLengthSuffixComparator {
         let (r_p, _) = rhs.split_at(rhs.len() - self.len);
         l_p == r_p
     }
+
+    fn name(&self) -> String {
+        format!("FixedLengthSuffixComparator({})", self.len)
+    }
 }
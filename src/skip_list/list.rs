@@ -1,49 +1,110 @@
-use std::{mem, ptr, u32};
+use std::{mem, ptr, slice, u32};
 use std::ops::Bound;
 use std::ptr::NonNull;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
-use std::sync::atomic::Ordering::Relaxed;
 
 use bytes::Bytes;
 use rand::Rng;
 
 use super::Allocator;
-use super::arena::Arena;
+use super::arena::{AllocError, Arena};
 use super::HEIGHT_INCREASE;
 use super::KeyComparator;
 use super::MAX_HEIGHT;
+use super::sync::{AtomicU32, AtomicUsize, Ordering};
 
-// Uses C layout to make sure tower is at the bottom
+// Uses C layout to make sure tower is at the bottom. The key and the value are stored in the arena
+// right after the used part of the tower, so a node owns no memory outside the arena.
 #[derive(Debug)]
 #[repr(C)]
 pub struct Node {
-    pub key: Bytes,
-    value: Bytes,
+    key_len: u32,
+    value_len: u32,
+    /// Offset of a node before this one at level 0. It is usually the previous node, but may be an
+    /// earlier one while a node is being inserted right before this one.
+    prev: AtomicU32,
     height: usize,
     tower: [AtomicU32; MAX_HEIGHT],
 }
 
 impl Node {
-    fn alloc(arena: &Arena, key: Bytes, value: Bytes, height: usize) -> u32 {
+    fn alloc(arena: &Arena, key: &[u8], value: &[u8], height: usize) -> Result<u32, AllocError> {
+        assert!(key.len() <= u32::MAX as usize && value.len() <= u32::MAX as usize);
         let align = mem::align_of::<Node>();
-        let size = mem::size_of::<Node>();
         // Not all values in Node::tower will be utilized.
-        let not_used = (MAX_HEIGHT - height - 1) * mem::size_of::<AtomicU32>();
-        let node_offset = arena.alloc(align, size - not_used);
+        let data_offset = Self::data_offset(height);
+        let node_offset = arena.alloc(align, data_offset + key.len() + value.len())?;
         unsafe {
             let node_ptr: *mut Node = arena.get_mut(node_offset);
-            let node = &mut *node_ptr;
-            ptr::write(&mut node.key, key);
-            ptr::write(&mut node.value, value);
-            node.height = height;
-            ptr::write_bytes(node.tower.as_mut_ptr(), 0, height + 1);
+            ptr::addr_of_mut!((*node_ptr).key_len).write(key.len() as u32);
+            ptr::addr_of_mut!((*node_ptr).value_len).write(value.len() as u32);
+            ptr::addr_of_mut!((*node_ptr).prev).write(AtomicU32::new(0));
+            ptr::addr_of_mut!((*node_ptr).height).write(height);
+            let tower = ptr::addr_of_mut!((*node_ptr).tower) as *mut AtomicU32;
+            for i in 0..=height {
+                ptr::write(tower.add(i), AtomicU32::new(0));
+            }
+            let data = (node_ptr as *mut u8).add(data_offset);
+            ptr::copy_nonoverlapping(key.as_ptr(), data, key.len());
+            ptr::copy_nonoverlapping(value.as_ptr(), data.add(key.len()), value.len());
         }
-        node_offset
+        Ok(node_offset)
+    }
+
+    /// Offset of the key from the start of a node of `height`.
+    fn data_offset(height: usize) -> usize {
+        mem::offset_of!(Node, tower) + (height + 1) * mem::size_of::<AtomicU32>()
+    }
+
+    /// Get the key of `node`, which must have been allocated by `Node::alloc` in an arena that
+    /// outlives `'a`.
+    pub unsafe fn key<'a>(node: *const Node) -> &'a [u8] {
+        let data = (node as *const u8).add(Self::data_offset((*node).height));
+        slice::from_raw_parts(data, (*node).key_len as usize)
+    }
+
+    /// Get the value of `node`, see `Node::key`.
+    pub unsafe fn value<'a>(node: *const Node) -> &'a [u8] {
+        let data = (node as *const u8).add(Self::data_offset((*node).height) + (*node).key_len as usize);
+        slice::from_raw_parts(data, (*node).value_len as usize)
     }
 
-    fn next_offset(&self, height: usize) -> u32 {
-        self.tower[height].load(Ordering::SeqCst)
+    /// Get the link of `node` at `level`. Nodes are only accessed through raw pointers, since a
+    /// reference to a `Node` would cover the unused part of its tower, which may hold its key or
+    /// lie past the end of the arena.
+    unsafe fn tower<'a>(node: *const Node, level: usize) -> &'a AtomicU32 {
+        &*(ptr::addr_of!((*node).tower) as *const AtomicU32).add(level)
+    }
+
+    /// Acquire pairs with the release of the CAS that linked the next node, so its key, value and
+    /// tower are visible once its offset is.
+    unsafe fn next_offset(node: *const Node, level: usize) -> u32 {
+        Self::tower(node, level).load(Ordering::Acquire)
+    }
+
+    unsafe fn prev_link<'a>(node: *const Node) -> &'a AtomicU32 {
+        &*ptr::addr_of!((*node).prev)
+    }
+}
+
+/// The nodes around a key at every level of a skiplist. `put_with_hint` reuses it to insert keys
+/// close to the previous one without searching from the head.
+#[derive(Clone, Debug)]
+pub struct Splice {
+    /// The height of the skiplist when the splice was computed. Levels above it are not set.
+    height: usize,
+    /// Offsets of the nodes before and after the key, `prev[0]` is 0 if the splice is not set.
+    prev: [u32; MAX_HEIGHT + 1],
+    next: [u32; MAX_HEIGHT + 1],
+}
+
+impl Default for Splice {
+    fn default() -> Self {
+        Self {
+            height: 0,
+            prev: [0; MAX_HEIGHT + 1],
+            next: [0; MAX_HEIGHT + 1],
+        }
     }
 }
 
@@ -51,6 +112,7 @@ impl Node {
 struct SkiplistCore {
     height: AtomicUsize,
     head: NonNull<Node>,
+    head_offset: u32,
     arena: Arena,
 }
 
@@ -61,21 +123,36 @@ pub struct Skiplist<C> {
 }
 
 impl<C> Skiplist<C> {
-    pub fn with_capacity(c: C, arena_size: u32) -> Skiplist<C> {
-        let arena = Arena::with_capacity(arena_size as usize);
-        let head_offset = Node::alloc(&arena, Bytes::new(), Bytes::new(), MAX_HEIGHT - 1);
+    /// Create a skiplist on a fixed arena of `arena_size` bytes. Panics if the arena is larger than
+    /// its offsets can address.
+    pub fn with_capacity(c: C, arena_size: usize) -> Skiplist<C> {
+        Self::with_arena(c, Arena::with_capacity(arena_size))
+    }
+
+    /// Create a skiplist on `arena`, e.g. a chained arena for a very large memtable.
+    pub fn with_arena(c: C, arena: Arena) -> Skiplist<C> {
+        let head_offset = Node::alloc(&arena, &[], &[], MAX_HEIGHT - 1)
+            .expect("arena is too small for the head node");
         let head = unsafe { NonNull::new_unchecked(arena.get_mut(head_offset)) };
         Skiplist {
             core: Arc::new(SkiplistCore {
                 height: AtomicUsize::new(0),
                 head,
+                head_offset,
                 arena,
             }),
             c,
         }
     }
 
-    fn random_height(&self) -> usize {
+    #[cfg(loom)]
+    fn random_height(&self, key: &[u8]) -> usize {
+        // Loom replays every execution, so heights must not depend on a random generator.
+        key.last().map_or(0, |b| *b as usize % 2)
+    }
+
+    #[cfg(not(loom))]
+    fn random_height(&self, _key: &[u8]) -> usize {
         let mut rng = rand::thread_rng();
         for h in 0..(MAX_HEIGHT - 1) {
             if !rng.gen_ratio(HEIGHT_INCREASE, u32::MAX) {
@@ -85,8 +162,11 @@ impl<C> Skiplist<C> {
         MAX_HEIGHT - 1
     }
 
+    /// The height is only a hint of where searches start. A stale height starts the search too
+    /// low, which is still correct, and links above a new height are found empty until they are
+    /// set, so it needs no ordering.
     fn height(&self) -> usize {
-        self.core.height.load(Ordering::SeqCst)
+        self.core.height.load(Ordering::Relaxed)
     }
 
     pub fn println_list(&self) {
@@ -97,11 +177,10 @@ impl<C> Skiplist<C> {
                 let mut cur = head;
                 print!("level {} ", i);
                 while !cur.is_null() {
-                    let node = &*cur;
-                    print!("{} ", String::from_utf8_unchecked(node.key.to_vec()));
-                    let ht = node.height;
+                    print!("{} ", String::from_utf8_lossy(Node::key(cur)));
+                    let ht = (*cur).height;
                     if ht >= i {
-                        cur = self.core.arena.get_mut(node.tower[i].load(Relaxed));
+                        cur = self.core.arena.get_mut(Node::next_offset(cur, i));
                     } else {
                         cur = ptr::null_mut::<Node>();
                     }
@@ -113,9 +192,9 @@ impl<C> Skiplist<C> {
 }
 
 impl<C: KeyComparator> Skiplist<C> {
-    pub fn find_near_value(&self, key: &[u8], less: bool, allow_equal: bool) -> &Bytes {
+    pub fn find_near_value(&self, key: &[u8], less: bool, allow_equal: bool) -> &[u8] {
         let ptr = self.find_near(key, less, allow_equal);
-        unsafe { &(*ptr).value }
+        unsafe { Node::value(ptr) }
     }
 
     pub fn find_near(&self, key: &[u8], less: bool, allow_equal: bool) -> *const Node {
@@ -123,7 +202,7 @@ impl<C: KeyComparator> Skiplist<C> {
             let mut cursor: *const Node = self.core.head.as_ptr();
             let mut level = self.height();
             loop {
-                let next_offset = (&*cursor).next_offset(level);
+                let next_offset = Node::next_offset(cursor, level);
                 if next_offset == 0 {
                     if level > 0 {
                         level -= 1;
@@ -135,18 +214,17 @@ impl<C: KeyComparator> Skiplist<C> {
                     return cursor;
                 }
                 let next_ptr: *mut Node = self.core.arena.get_mut(next_offset);
-                let next = &*next_ptr;
-                let res = self.c.compare_key(key, &next.key);
+                let res = self.c.compare_key(key, Node::key(next_ptr));
                 if res == std::cmp::Ordering::Greater {
                     cursor = next_ptr;
                     continue;
                 }
                 if res == std::cmp::Ordering::Equal {
                     if allow_equal {
-                        return next;
+                        return next_ptr;
                     }
                     if !less {
-                        let offset = next.next_offset(0);
+                        let offset = Node::next_offset(next_ptr, 0);
                         return if offset != 0 {
                             self.core.arena.get_mut(offset)
                         } else {
@@ -167,7 +245,7 @@ impl<C: KeyComparator> Skiplist<C> {
                     continue;
                 }
                 if !less {
-                    return next;
+                    return next_ptr;
                 }
                 if cursor == self.core.head.as_ptr() {
                     return ptr::null();
@@ -177,89 +255,134 @@ impl<C: KeyComparator> Skiplist<C> {
         }
     }
 
-    unsafe fn find_splice_for_level(&self, key: &[u8], mut before: *mut Node, level: usize) -> (*mut Node, *mut Node) {
+    /// Find the offsets of the nodes between which `key` belongs at `level`, starting from
+    /// `before`, or the offset of the node with `key` twice if it exists.
+    unsafe fn find_splice_for_level(&self, key: &[u8], mut before: u32, level: usize) -> (u32, u32) {
         loop {
-            let next_offset = (&*before).next_offset(level);
-            if next_offset == 0 {
-                return (before, ptr::null_mut());
+            let next = Node::next_offset(self.node(before), level);
+            if next == 0 {
+                return (before, 0);
             }
-            let next_ptr: *mut Node = self.core.arena.get_mut(next_offset);
-            let next_node = &*next_ptr;
-            match self.c.compare_key(&key, &next_node.key) {
-                std::cmp::Ordering::Equal => return (next_ptr, next_ptr),
-                std::cmp::Ordering::Less => return (before, next_ptr),
-                _ => before = next_ptr,
+            match self.c.compare_key(key, Node::key(self.node(next))) {
+                std::cmp::Ordering::Equal => return (next, next),
+                std::cmp::Ordering::Less => return (before, next),
+                _ => before = next,
             }
         }
     }
 
-    pub fn put(&self, key: impl Into<Bytes>, value: impl Into<Bytes>) -> Option<(Bytes, Bytes)> {
-        let (key, value) = (key.into(), value.into());
+    /// Insert a key-value pair, copying it into the arena. Returns the pair back if the key exists
+    /// with another value, and an `AllocError` if it does not fit in the arena, in which case the
+    /// skiplist is unchanged.
+    pub fn put(&self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Result<Option<(Bytes, Bytes)>, AllocError> {
+        self.put_with_hint(key, value, &mut Splice::default())
+    }
+
+    /// Insert key-value pairs, which should be sorted by key. Each put starts from the splice of
+    /// the previous one instead of searching from the head, which makes inserting sorted keys
+    /// O(1) on average. Returns the pairs whose key exists with another value. On an `AllocError`,
+    /// the pairs before the one that did not fit are inserted.
+    pub fn put_sorted_batch<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &self,
+        items: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Vec<(Bytes, Bytes)>, AllocError> {
+        let mut splice = Splice::default();
+        let mut conflicts = vec![];
+        for (key, value) in items {
+            conflicts.extend(self.put_with_hint(key, value, &mut splice)?);
+        }
+        Ok(conflicts)
+    }
+
+    /// Like `put`, but reuses the splice of a previous put to the same skiplist. Only the levels
+    /// at which the splice does not surround `key` are searched again, which is cheap when `key`
+    /// is close to the previous one. The splice is updated to the position of `key`.
+    pub fn put_with_hint(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+        splice: &mut Splice,
+    ) -> Result<Option<(Bytes, Bytes)>, AllocError> {
+        let (key, value) = (key.as_ref(), value.as_ref());
         let mut list_height = self.height();
-        let mut prev = [ptr::null_mut(); MAX_HEIGHT + 1];
-        let mut next = [ptr::null_mut(); MAX_HEIGHT + 1];
-        prev[list_height + 1] = self.core.head.as_ptr();
-        next[list_height + 1] = ptr::null_mut();
-        for i in (0..=list_height).rev() {
-            let (p, n) = unsafe { self.find_splice_for_level(&key, prev[i + 1], i) };
+        let recompute_height = self.splice_recompute_height(key, splice, list_height);
+        // Offsets of the splice at each level, 0 where it is not searched yet.
+        let (prev, next) = (&mut splice.prev, &mut splice.next);
+        prev[list_height + 1] = self.core.head_offset;
+        prev[list_height + 2..].fill(0);
+        splice.height = list_height;
+        for i in (0..recompute_height).rev() {
+            let (p, n) = unsafe { self.find_splice_for_level(key, prev[i + 1], i) };
             prev[i] = p;
             next[i] = n;
             if p == n {
-                unsafe {
-                    if (*p).value != value {
-                        return Some((key, value));
-                    }
+                // The levels below were not searched for `key`, so the splice is not consistent.
+                prev[0] = 0;
+                if unsafe { Node::value(self.node(p)) } != value {
+                    return Ok(Some((Bytes::copy_from_slice(key), Bytes::copy_from_slice(value))));
                 }
-                return None;
+                return Ok(None);
             }
         }
 
-        let height = self.random_height();
-        let node_offset = Node::alloc(&self.core.arena, key, value, height);
+        let height = self.random_height(key);
+        let node_offset = Node::alloc(&self.core.arena, key, value, height)?;
+        splice.height = list_height.max(height);
         while height > list_height {
             match self.core.height.compare_exchange_weak(
                 list_height,
                 height,
-                Ordering::SeqCst,
-                Ordering::SeqCst,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
             ) {
                 Ok(_) => break,
                 Err(h) => list_height = h,
             }
         }
-        let x: &mut Node = unsafe { &mut *self.core.arena.get_mut(node_offset) };
+        let x: *const Node = unsafe { self.core.arena.get_mut(node_offset) };
         for i in 0..=height {
             loop {
-                if prev[i].is_null() {
+                if prev[i] == 0 {
                     assert!(i > 1);
-                    let (p, n) =
-                        unsafe { self.find_splice_for_level(&x.key, self.core.head.as_ptr(), i) };
+                    let (p, n) = unsafe { self.find_splice_for_level(key, self.core.head_offset, i) };
                     prev[i] = p;
                     next[i] = n;
                     assert_ne!(p, n);
                 }
-                let next_offset = self.core.arena.offset(next[i]);
-                x.tower[i].store(next_offset, Ordering::SeqCst);
-                match unsafe { &*prev[i] }.tower[i].compare_exchange(
-                    next_offset,
+                // The links of x are published by the release of the CAS below. Its release also
+                // carries over the nodes after x, which this thread acquired while searching, so a
+                // reader that sees x sees them too. A failed CAS is followed by a new search, which
+                // acquires what it reads, so it needs no ordering.
+                unsafe { Node::tower(x, i) }.store(next[i], Ordering::Relaxed);
+                if i == 0 {
+                    unsafe { Node::prev_link(x) }.store(prev[0], Ordering::Relaxed);
+                }
+                match unsafe { Node::tower(self.node(prev[i]), i) }.compare_exchange(
+                    next[i],
                     node_offset,
-                    Ordering::SeqCst,
-                    Ordering::SeqCst,
+                    Ordering::Release,
+                    Ordering::Relaxed,
                 ) {
-                    Ok(_) => break,
+                    Ok(_) => {
+                        prev[i] = node_offset;
+                        // Point the next node back at x. A concurrent insert between x and the
+                        // next node may be overwritten, which only leaves its link a node early.
+                        if i == 0 && next[0] != 0 {
+                            unsafe { Node::prev_link(self.node(next[0])) }.store(node_offset, Ordering::Release);
+                        }
+                        break;
+                    }
                     Err(_) => {
-                        let (p, n) = unsafe { self.find_splice_for_level(&x.key, prev[i], i) };
+                        let (p, n) = unsafe { self.find_splice_for_level(key, prev[i], i) };
                         if p == n {
+                            // Another writer inserted the key first. The node is left unlinked in
+                            // the arena.
                             assert_eq!(i, 0);
-                            if unsafe { &*p }.value != x.value {
-                                let key = mem::replace(&mut x.key, Bytes::new());
-                                let value = mem::replace(&mut x.value, Bytes::new());
-                                return Some((key, value));
-                            }
-                            unsafe {
-                                ptr::drop_in_place(x);
+                            prev[0] = 0;
+                            if unsafe { Node::value(self.node(p)) } != value {
+                                return Ok(Some((Bytes::copy_from_slice(key), Bytes::copy_from_slice(value))));
                             }
-                            return None;
+                            return Ok(None);
                         }
                         prev[i] = p;
                         next[i] = n;
@@ -267,12 +390,57 @@ impl<C: KeyComparator> Skiplist<C> {
                 }
             }
         }
-        None
+        Ok(None)
+    }
+
+    /// Get how many levels of `splice`, from the bottom, must be searched again for `key`. A level
+    /// can be kept if its nodes are still adjacent and surround `key`, and then so can the levels
+    /// above it, which surround a wider range.
+    fn splice_recompute_height(&self, key: &[u8], splice: &Splice, list_height: usize) -> usize {
+        if splice.prev[0] == 0 || splice.height != list_height {
+            return list_height + 1;
+        }
+        (0..=list_height)
+            .find(|&level| unsafe {
+                let (prev, next) = (splice.prev[level], splice.next[level]);
+                Node::next_offset(self.node(prev), level) == next
+                    && (prev == self.core.head_offset
+                        || self.c.compare_key(key, Node::key(self.node(prev))) == std::cmp::Ordering::Greater)
+                    && (next == 0 || self.c.compare_key(key, Node::key(self.node(next))) == std::cmp::Ordering::Less)
+            })
+            .unwrap_or(list_height + 1)
+    }
+
+    /// Find the node before `node` at level 0, or null if it is the first one. Starts from the
+    /// backward link of `node`, which is at most a few nodes early, so it is amortized O(1).
+    fn find_prev(&self, node: *const Node) -> *const Node {
+        unsafe {
+            let mut cursor = Node::prev_link(node).load(Ordering::Acquire);
+            loop {
+                let next = Node::next_offset(self.node(cursor), 0);
+                if ptr::eq(self.node(next), node) {
+                    break;
+                }
+                cursor = next;
+            }
+            if cursor == self.core.head_offset {
+                return ptr::null();
+            }
+            self.node(cursor)
+        }
+    }
+
+    fn node(&self, offset: u32) -> *mut Node {
+        unsafe { self.core.arena.get_mut(offset) }
+    }
+
+    pub fn comparator(&self) -> &C {
+        &self.c
     }
 
     pub fn is_empty(&self) -> bool {
         let node = self.core.head.as_ptr();
-        let next_offset = unsafe { (&*node).next_offset(0) };
+        let next_offset = unsafe { Node::next_offset(node, 0) };
         next_offset == 0
     }
 
@@ -280,7 +448,7 @@ impl<C: KeyComparator> Skiplist<C> {
         let mut node = self.core.head.as_ptr();
         let mut count = 0;
         loop {
-            let next = unsafe { (&*node).next_offset(0) };
+            let next = unsafe { Node::next_offset(node, 0) };
             if next != 0 {
                 count += 1;
                 node = unsafe { self.core.arena.get_mut(next) };
@@ -294,7 +462,7 @@ impl<C: KeyComparator> Skiplist<C> {
         let mut node = self.core.head.as_ptr();
         let mut level = self.height();
         loop {
-            let next = unsafe { (&*node).next_offset(level) };
+            let next = unsafe { Node::next_offset(node, level) };
             if next != 0 {
                 node = unsafe { self.core.arena.get_mut(next) };
                 continue;
@@ -309,13 +477,13 @@ impl<C: KeyComparator> Skiplist<C> {
         }
     }
 
-    pub fn get(&self, key: &[u8]) -> Option<&Bytes> {
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
         let node = unsafe { self.find_near(key, false, true) };
         if node.is_null() {
             return None;
         }
-        if self.c.same_key(&unsafe { &*node }.key, key) {
-            return unsafe { Some(&(*node).value) };
+        if self.c.same_key(unsafe { Node::key(node) }, key) {
+            return unsafe { Some(Node::value(node)) };
         }
         None
     }
@@ -331,30 +499,12 @@ impl<C: KeyComparator> Skiplist<C> {
         RangeRef::create(self, (lower, upper))
     }
 
-    pub fn mem_size(&self) -> u32 {
+    /// Get the memory used by the skiplist, including its keys and values.
+    pub fn mem_size(&self) -> usize {
         self.core.arena.len()
     }
 }
 
-impl Drop for SkiplistCore {
-    fn drop(&mut self) {
-        let mut node = self.head.as_ptr();
-        loop {
-            let next = unsafe { (&*node).next_offset(0) };
-            if next != 0 {
-                let next_ptr = unsafe { self.arena.get_mut(next) };
-                unsafe {
-                    ptr::drop_in_place(node);
-                }
-                node = next_ptr;
-                continue;
-            }
-            unsafe { ptr::drop_in_place(node) };
-            return;
-        }
-    }
-}
-
 impl<C> AsRef<Skiplist<C>> for Skiplist<C> {
     fn as_ref(&self) -> &Skiplist<C> {
         self
@@ -421,7 +571,7 @@ impl<'a, C: KeyComparator> RangeRef<'a, C> {
         range_it
     }
 
-    pub fn next(&mut self) -> Option<(&Bytes, &Bytes)> {
+    pub fn next(&mut self) -> Option<(&[u8], &[u8])> {
         if self.cursor.is_null()
         {
             return None;
@@ -431,9 +581,9 @@ impl<'a, C: KeyComparator> RangeRef<'a, C> {
             return match &self.end {
                 Bound::Included(_) => {
                     unsafe {
+                        let node = self.cursor;
                         self.cursor = ptr::null();// set null
-                        let node = &*self.cursor;
-                        Some((&node.key, &node.value))
+                        Some((Node::key(node), Node::value(node)))
                     }
                 }
                 Bound::Excluded(_) => { None }
@@ -443,7 +593,7 @@ impl<'a, C: KeyComparator> RangeRef<'a, C> {
         if self.cursor == self.head {
             if let Bound::Excluded(s) = &self.start {
                 unsafe {
-                    let cursor_offset = (&*self.cursor).next_offset(0);
+                    let cursor_offset = Node::next_offset(self.cursor, 0);
                     self.cursor = self.list.core.arena.get_mut(cursor_offset);
                 }
                 return self.next();
@@ -451,10 +601,10 @@ impl<'a, C: KeyComparator> RangeRef<'a, C> {
         }
 
         unsafe {
-            let node = &*self.cursor;
-            let cursor_offset = (&*self.cursor).next_offset(0);
+            let node = self.cursor;
+            let cursor_offset = Node::next_offset(self.cursor, 0);
             self.cursor = self.list.core.arena.get_mut(cursor_offset);
-            Some((&node.key, &node.value))
+            Some((Node::key(node), Node::value(node)))
         }
     }
 
@@ -472,29 +622,27 @@ impl<'a, C: KeyComparator> IterRef<'a, C> {
         !self.cursor.is_null()
     }
 
-    pub fn key(&self) -> &Bytes {
+    pub fn key(&self) -> &[u8] {
         assert!(self.valid());
-        unsafe { &(*self.cursor).key }
+        unsafe { Node::key(self.cursor) }
     }
 
-    pub fn value(&self) -> &Bytes {
+    pub fn value(&self) -> &[u8] {
         assert!(self.valid());
-        unsafe { &(*self.cursor).value }
+        unsafe { Node::value(self.cursor) }
     }
 
     pub fn next(&mut self) {
         assert!(self.valid());
         unsafe {
-            let cursor_offset = (&*self.cursor).next_offset(0);
+            let cursor_offset = Node::next_offset(self.cursor, 0);
             self.cursor = self.list.core.arena.get_mut(cursor_offset);
         }
     }
 
     pub fn prev(&mut self) {
         assert!(self.valid());
-        unsafe {
-            self.cursor = self.list.find_near(self.key(), true, false);
-        }
+        self.cursor = self.list.find_prev(self.cursor);
     }
 
     pub fn seek(&mut self, target: &[u8]) {
@@ -511,7 +659,7 @@ impl<'a, C: KeyComparator> IterRef<'a, C> {
 
     pub fn seek_to_first(&mut self) {
         unsafe {
-            let cursor_offset = (&*self.list.core.head.as_ptr()).next_offset(0);
+            let cursor_offset = Node::next_offset(self.list.core.head.as_ptr(), 0);
             self.cursor = self.list.core.arena.get_mut(cursor_offset);
         }
     }
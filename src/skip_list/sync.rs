@@ -0,0 +1,13 @@
+//! The synchronization primitives of the skiplist and its arena. They are loom's when built with
+//! `--cfg loom`, so that the loom tests can explore the interleavings of concurrent operations.
+
+#[cfg(loom)]
+pub(crate) use loom::sync::{
+    atomic::{AtomicPtr, AtomicU32, AtomicUsize, Ordering},
+    Mutex,
+};
+#[cfg(not(loom))]
+pub(crate) use std::sync::{
+    atomic::{AtomicPtr, AtomicU32, AtomicUsize, Ordering},
+    Mutex,
+};
@@ -11,59 +11,50 @@ use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
-use bytes::{Buf, BufMut, Bytes};
+use bytes::{Buf, BufMut};
 
 use crate::block::Block;
+use crate::range_tombstone::RangeTombstone;
+use crate::skip_list::{BytewiseComparator, KeyComparator};
+
+pub use footer::{Footer, FORMAT_VERSION};
+pub use properties::TableProperties;
+pub use sst_file_writer::{SstFileWriter, validate_external_file};
 
 mod iterator;
 mod builder;
+mod footer;
+mod properties;
+mod sst_file_writer;
+#[cfg(test)]
 mod tests;
+mod writer;
 
 pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
 
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct BlockMeta {
-    /// Offset of this data block.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockHandle {
+    /// Offset of the block in the file.
     pub offset: u64,
-    /// The first key of the data block, mainly used for index purpose.
-    pub first_key: Bytes,
+    /// Encoded length of the block.
+    pub size: u64,
 }
 
-impl BlockMeta {
-    /// Encode block meta to a buffer.
-    /// You may add extra fields to the buffer,
-    /// in order to help keep track of `first_key` when decoding from the same buffer in the future.
-    pub fn encode_block_meta(block_meta: &[BlockMeta], buf: &mut Vec<u8>) {
-        let mut size = 0;
-        for meta in block_meta {
-            size += std::mem::size_of::<u64>();
-            size += std::mem::size_of::<u16>();
-            size += meta.first_key.len();
-        }
-        let original_len = buf.len();
-        for meta in block_meta {
-            buf.put_u64(meta.offset);
-            buf.put_u16(meta.first_key.len() as u16);
-            buf.put_slice(&meta.first_key)
-        }
-        assert_eq!(size, buf.len() - original_len);
-    }
+impl BlockHandle {
+    pub const ENCODED_LEN: usize = size_of::<u64>() * 2;
 
-    /// Decode block meta from a buffer.
-    pub fn decode_block_meta(mut buf: impl Buf) -> Vec<BlockMeta> {
-        let mut vec = vec![];
-        while buf.has_remaining() {
-            let offset = buf.get_u64();
-            let len = buf.get_u16() as usize;
-            let first_key = buf.copy_to_bytes(len);
-            vec.push(BlockMeta { offset, first_key });
-        }
-        vec
+    /// Encode the handle to a buffer.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u64(self.offset);
+        buf.put_u64(self.size);
     }
 
-    pub fn size(&self) -> usize {
-        size_of::<u64>() + size_of::<u16>() + self.first_key.len()
+    /// Decode a handle from a buffer.
+    pub fn decode(mut buf: impl Buf) -> Self {
+        let offset = buf.get_u64();
+        let size = buf.get_u64();
+        Self { offset, size }
     }
 }
 
@@ -74,7 +65,6 @@ impl FileObject {
     pub fn read(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
         let mut buf = vec![0; len as usize];
         let r = self.0.seek_read(&mut buf, offset)?;
-        println!("read buf {:?}", buf);
         Ok(buf)
     }
 
@@ -103,25 +93,69 @@ impl FileObject {
     }
 }
 
-/// -------------------------------------------------------------------------------------------------------
-/// |              Data Block             |             Meta Block              |          Extra          |
-/// -------------------------------------------------------------------------------------------------------
-/// | Data Block #1 | ... | Data Block #N | Meta Block #1 | ... | Meta Block #N | Meta Block Offset (u64) |
-/// -------------------------------------------------------------------------------------------------------
-pub struct SsTable {
+/// How the index of an SsTable is laid out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexType {
+    /// A single index block that maps separators to data blocks.
+    Flat = 0,
+    /// A top-level index block that maps separators to index partitions, each of which is a flat
+    /// index over a run of consecutive data blocks.
+    Partitioned = 1,
+}
+
+impl IndexType {
+    fn decode(raw: u8) -> Result<Self> {
+        match raw {
+            0 => Ok(IndexType::Flat),
+            1 => Ok(IndexType::Partitioned),
+            _ => Err(anyhow!("unknown index type {}", raw)),
+        }
+    }
+}
+
+/// ---------------------------------------------------------------------------------------------------
+/// |              Data Block             |     Meta Block     |   Index Block   |        Extra         |
+/// ---------------------------------------------------------------------------------------------------
+/// | Data Block #1 | ... | Data Block #N | Range Del Block | Properties Block | Index Block | Footer |
+/// ---------------------------------------------------------------------------------------------------
+///
+/// The footer holds the handles of the other blocks, a format version and a magic number, see `Footer`.
+/// The range deletion block holds the `RangeTombstone`s of the table, and is only written if there
+/// are any. The properties block holds the `TableProperties` of the table.
+///
+/// The index block uses the data block format, with u32 instead of u16 offsets since it grows with
+/// the table, see `Block::encode_wide`. So do the range deletion and properties blocks. The key of
+/// the `i`-th index entry is a separator that is `>=` every key of data block `i` and `<` every key
+/// of data block `i + 1`, and its value is the encoded `BlockHandle` of data block `i`.
+///
+/// With a partitioned index, the flat index is cut into partitions that are written between the data
+/// blocks. The index block at the end is then the top-level index: the key of each entry is the last
+/// separator of a partition, and its value is the partition's `BlockHandle` followed by the index of
+/// its first data block (u64).
+///
+/// Keys, separators and tombstones are ordered by the comparator `C`, whose name is recorded in the
+/// properties block and checked when the table is opened.
+pub struct SsTable<C: KeyComparator = BytewiseComparator> {
     /// The actual storage unit of SsTable, the format is as above.
     file: FileObject,
-    /// The meta blocks that hold info for data blocks.
-    block_metas: Vec<BlockMeta>,
-    /// The offset that indicates the start point of meta blocks in `file`.
-    block_meta_offset: u64,
+    index_type: IndexType,
+    /// The handle of the index block, or of the top-level index block if the index is partitioned.
+    index_handle: BlockHandle,
+    /// The index block, pinned in memory only when there is no block cache to hold it.
+    /// Index partitions are never pinned.
+    index_block: Option<Arc<Block>>,
+    num_of_blocks: usize,
+    properties: TableProperties,
+    /// Range tombstones of the table, which delete keys of older tables.
+    range_tombstones: Vec<RangeTombstone>,
     sst_id: usize,
     block_cache: Option<Arc<BlockCache>>,
+    comparator: C,
 }
 
-impl fmt::Display for SsTable {
+impl<C: KeyComparator> fmt::Display for SsTable<C> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "block meta {:?}, offset {:?}", &self.block_metas, self.block_meta_offset)
+        write!(f, "{:?} index block {:?}, blocks {}", self.index_type, self.index_handle, self.num_of_blocks)
     }
 }
 
@@ -131,58 +165,205 @@ impl SsTable {
         Self::open(0, file, None)
     }
 
-    /// Open SSTable from a file.
+    /// Open SSTable from a file written with the bytewise comparator.
     pub fn open(id: usize, file: FileObject, block_cache: Option<Arc<BlockCache>>) -> Result<Self> {
+        Self::open_with_comparator(id, file, block_cache, BytewiseComparator)
+    }
+}
+
+impl<C: KeyComparator> SsTable<C> {
+    /// Open SSTable from a file. Fails if the table was written with a comparator of another name.
+    pub fn open_with_comparator(
+        id: usize,
+        file: FileObject,
+        block_cache: Option<Arc<BlockCache>>,
+        comparator: C,
+    ) -> Result<Self> {
         let len = file.size();
-        let raw_meta_off = file.read(len - size_of::<u64>() as u64, size_of::<u64>() as u64)?;
-        let meta_off = (&raw_meta_off[..]).get_u64();
-        let raw_meta = file.read(meta_off, len - size_of::<u64>() as u64 - meta_off)?;
-        Ok(Self {
+        let footer_len = Footer::ENCODED_LEN as u64;
+        if len < footer_len {
+            return Err(anyhow!("sst file is truncated: {} bytes is shorter than the footer", len));
+        }
+        let raw_footer = file.read(len - footer_len, footer_len)?;
+        let footer = Footer::decode(&raw_footer, len)?;
+        let raw_properties = file.read(footer.properties_handle.offset, footer.properties_handle.size)?;
+        let properties = TableProperties::decode(&raw_properties)?;
+        if properties.comparator != comparator.name() {
+            return Err(anyhow!(
+                "sst was written with comparator {:?}, cannot open it with {:?}",
+                properties.comparator,
+                comparator.name()
+            ));
+        }
+        let range_tombstones = if footer.range_del_handle.size == 0 {
+            Vec::new()
+        } else {
+            let raw_range_del = file.read(footer.range_del_handle.offset, footer.range_del_handle.size)?;
+            RangeTombstone::decode_block(&raw_range_del)?
+        };
+        Self::from_index(id, file, footer, properties, range_tombstones, block_cache, comparator)
+    }
+
+    /// Create a table from the decoded footer, properties and range tombstones of `file`, reading
+    /// its index block.
+    pub(super) fn from_index(
+        id: usize,
+        file: FileObject,
+        footer: Footer,
+        properties: TableProperties,
+        range_tombstones: Vec<RangeTombstone>,
+        block_cache: Option<Arc<BlockCache>>,
+        comparator: C,
+    ) -> Result<Self> {
+        let mut table = Self {
             file,
-            block_metas: BlockMeta::decode_block_meta(&raw_meta[..]),
-            block_meta_offset: meta_off,
+            index_type: footer.index_type,
+            index_handle: footer.index_handle,
+            index_block: None,
+            num_of_blocks: 0,
+            properties,
+            range_tombstones,
             sst_id: id,
             block_cache,
-        })
+            comparator,
+        };
+        let index = table.read_index_block()?;
+        table.num_of_blocks = match table.index_type {
+            IndexType::Flat => index.num_of_entries(),
+            IndexType::Partitioned if index.num_of_entries() == 0 => 0,
+            IndexType::Partitioned => {
+                let (handle, first_block_idx) = decode_partition_entry(index.value_at(index.num_of_entries() - 1));
+                first_block_idx + table.read_block_at_cached(handle, Block::decode_wide)?.num_of_entries()
+            }
+        };
+        if table.block_cache.is_none() {
+            table.index_block = Some(index);
+        }
+        Ok(table)
     }
 
-    /// Read a block from the disk.
-    pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
-        assert!(block_idx < self.block_metas.len());
-        let block_off_start = self.block_metas[block_idx].offset;
-        let block_off_end = self.block_metas
-            .get(block_idx + 1)
-            .map_or(self.block_meta_offset, |m| m.offset);
-        let raw_block = self.file.read(block_off_start, block_off_end - block_off_start)?;
-
-        let b = Block::decode(&raw_block);
-        Ok(Arc::new(b))
+    /// Read the block pointed by `handle`, which is a data block if `decode` is `Block::decode` or
+    /// an index block if it is `Block::decode_wide`.
+    fn read_block_at(&self, handle: BlockHandle, decode: fn(&[u8]) -> Block) -> Result<Arc<Block>> {
+        let raw_block = self.file.read(handle.offset, handle.size)?;
+        Ok(Arc::new(decode(&raw_block)))
     }
 
-    /// Read a block from disk, with block cache. (Day 4)
-    pub fn read_block_cached(&self, block_idx: usize) -> Result<Arc<Block>> {
+    /// Read the block pointed by `handle` through the block cache, keyed by its offset.
+    fn read_block_at_cached(&self, handle: BlockHandle, decode: fn(&[u8]) -> Block) -> Result<Arc<Block>> {
         if let Some(ref cache) = self.block_cache {
-            cache.try_get_with((self.sst_id, block_idx), || self.read_block(block_idx))
+            cache.try_get_with((self.sst_id, handle.offset as usize), || self.read_block_at(handle, decode))
                 .map_err(|e| anyhow!("{}", e))
         } else {
-            self.read_block(block_idx)
+            self.read_block_at(handle, decode)
+        }
+    }
+
+    /// Get the index block, from memory if it is pinned or through the block cache otherwise.
+    fn read_index_block(&self) -> Result<Arc<Block>> {
+        match self.index_block {
+            Some(ref index) => Ok(index.clone()),
+            None => self.read_block_at_cached(self.index_handle, Block::decode_wide),
+        }
+    }
+
+    fn block_handle(&self, block_idx: usize) -> Result<BlockHandle> {
+        assert!(block_idx < self.num_of_blocks);
+        let index = self.read_index_block()?;
+        match self.index_type {
+            IndexType::Flat => Ok(BlockHandle::decode(index.value_at(block_idx))),
+            IndexType::Partitioned => {
+                let (mut low, mut high) = (0, index.num_of_entries());
+                while low < high {
+                    let mid = low + (high - low) / 2;
+                    if decode_partition_entry(index.value_at(mid)).1 <= block_idx {
+                        low = mid + 1;
+                    } else {
+                        high = mid;
+                    }
+                }
+                let (handle, first_block_idx) = decode_partition_entry(index.value_at(low - 1));
+                let partition = self.read_block_at_cached(handle, Block::decode_wide)?;
+                Ok(BlockHandle::decode(partition.value_at(block_idx - first_block_idx)))
+            }
         }
     }
 
-    /// Find the block that may contain `key`.
-    /// Note: You may want to make use of the `first_key` stored in `BlockMeta`.
-    /// You may also assume the key-value pairs stored in each consecutive block are sorted.
-    pub fn find_block_idx(&self, key: &[u8]) -> usize {
-        for meta in &self.block_metas {
-            println!("{:?}", meta)
+    /// Read a block from the disk.
+    pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
+        self.read_block_at(self.block_handle(block_idx)?, Block::decode)
+    }
+
+    /// Read a block from disk, with block cache. (Day 4)
+    pub fn read_block_cached(&self, block_idx: usize) -> Result<Arc<Block>> {
+        self.read_block_at_cached(self.block_handle(block_idx)?, Block::decode)
+    }
+
+    /// Find the block that may contain `key`, which is the first block whose separator is `>= key`.
+    /// The index block is binary searched in place. If `key` is greater than every separator, the
+    /// last block is returned.
+    pub fn find_block_idx(&self, key: &[u8]) -> Result<usize> {
+        if self.num_of_blocks == 0 {
+            return Ok(0);
         }
-        self.block_metas.partition_point(|e| e.first_key <= key).saturating_sub(1)
+        let index = self.read_index_block()?;
+        let idx = match self.index_type {
+            IndexType::Flat => seek_index(&index, key, &self.comparator),
+            IndexType::Partitioned => {
+                let partition_idx = seek_index(&index, key, &self.comparator);
+                let (handle, first_block_idx) = decode_partition_entry(index.value_at(partition_idx));
+                let partition = self.read_block_at_cached(handle, Block::decode_wide)?;
+                first_block_idx + seek_index(&partition, key, &self.comparator)
+            }
+        };
+        Ok(idx.min(self.num_of_blocks.saturating_sub(1)))
     }
 
     /// Get number of data blocks.
     pub fn num_of_blocks(&self) -> usize {
-        self.block_metas.len()
+        self.num_of_blocks
+    }
+
+    /// Get the properties recorded when the table was built.
+    pub fn properties(&self) -> &TableProperties {
+        &self.properties
     }
+
+    /// Get the range tombstones of the table.
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    /// Returns whether the table may contain keys in `[smallest, largest]`. Compaction uses this to
+    /// pick the tables overlapping a key range without reading their blocks.
+    pub fn overlaps(&self, smallest: &[u8], largest: &[u8]) -> bool {
+        self.properties.overlaps(&self.comparator, smallest, largest)
+    }
+
+    /// Get the comparator the table is ordered by.
+    pub fn comparator(&self) -> &C {
+        &self.comparator
+    }
+}
+
+/// Returns the first entry of `index` whose separator is `>= key`, or the last entry if there is none.
+fn seek_index<C: KeyComparator>(index: &Block, key: &[u8], c: &C) -> usize {
+    let (mut low, mut high) = (0, index.num_of_entries());
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if c.compare_key(index.key_at(mid), key) == std::cmp::Ordering::Less {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    low.min(index.num_of_entries().saturating_sub(1))
+}
+
+/// Decode a top-level index entry into the partition handle and the index of its first data block.
+fn decode_partition_entry(mut buf: &[u8]) -> (BlockHandle, usize) {
+    let handle = BlockHandle::decode(&mut buf);
+    (handle, buf.get_u64() as usize)
 }
 
 #[test]
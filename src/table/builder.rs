@@ -9,89 +9,245 @@ use anyhow::Result;
 use bytes::BufMut;
 
 use crate::block::block_builder::BlockBuilder;
+use crate::range_tombstone::RangeTombstone;
+use crate::skip_list::{BytewiseComparator, KeyComparator};
 
-use super::{BlockCache, BlockMeta, FileObject, SsTable};
+use super::{BlockCache, BlockHandle, Footer, FORMAT_VERSION, IndexType, SsTable, TableProperties};
+use super::writer::{StreamWriter, TableWriter};
 
-/// Builds an SSTable from key-value pairs.
-pub struct SsTableBuilder {
-    data: Vec<u8>,
-    pub(super) meta: Vec<BlockMeta>,
+/// Builds an SSTable from key-value pairs, which must be added in the order of the comparator `C`.
+pub struct SsTableBuilder<C: KeyComparator = BytewiseComparator> {
+    writer: TableWriter,
+    /// Builds the flat index, or the current index partition if the index is partitioned.
+    index_builder: BlockBuilder,
+    /// Builds the top-level index over the partitions. Only used when the index is partitioned.
+    top_index_builder: BlockBuilder,
+    /// Target size of an index partition, `None` for a flat index.
+    index_partition_size: Option<usize>,
+    /// The last separator added to `index_builder`.
+    last_separator: Vec<u8>,
+    /// The index of the first data block in the current index partition.
+    partition_first_block: usize,
+    pub(super) num_of_blocks: usize,
     block_builder: BlockBuilder,
-    start_key: Vec<u8>,
+    last_key: Vec<u8>,
     block_size: usize,
+    properties: TableProperties,
+    range_tombstones: Vec<RangeTombstone>,
+    comparator: C,
     // Add other fields you need.
 }
 
 impl SsTableBuilder {
-    /// Create a builder based on target block size.
+    /// Create a builder based on target block size. The table is kept in memory until it is built.
     pub fn new(block_size: usize) -> Self {
+        Self::new_with_comparator(block_size, BytewiseComparator)
+    }
+
+    /// Create a builder that streams the table to `path`, appending each block as it is finished.
+    /// The table must later be built at the same `path`, and the file is byte-identical to one built
+    /// in memory.
+    pub fn create(path: impl AsRef<Path>, block_size: usize) -> Result<Self> {
+        Self::create_with_comparator(path, block_size, BytewiseComparator)
+    }
+
+    /// Create a builder that writes a two-level index, cutting the index into partitions of about
+    /// `index_partition_size` bytes. Only the top-level index is read when the table is opened, and
+    /// partitions are loaded on demand through the block cache.
+    pub fn with_partitioned_index(block_size: usize, index_partition_size: usize) -> Self {
+        let mut builder = Self::new(block_size);
+        builder.set_index_partition_size(index_partition_size);
+        builder
+    }
+}
+
+impl<C: KeyComparator> SsTableBuilder<C> {
+    /// Like `new`, for keys ordered by `comparator`.
+    pub fn new_with_comparator(block_size: usize, comparator: C) -> Self {
+        Self::with_writer(block_size, TableWriter::Memory(Vec::default()), comparator)
+    }
+
+    /// Like `create`, for keys ordered by `comparator`.
+    pub fn create_with_comparator(path: impl AsRef<Path>, block_size: usize, comparator: C) -> Result<Self> {
+        let writer = StreamWriter::create(path.as_ref())?;
+        Ok(Self::with_writer(block_size, TableWriter::File(writer), comparator))
+    }
+
+    fn with_writer(block_size: usize, writer: TableWriter, comparator: C) -> Self {
         Self {
-            data: Vec::default(),
-            meta: Vec::default(),
+            writer,
+            index_builder: BlockBuilder::new_unbounded(),
+            top_index_builder: BlockBuilder::new_unbounded(),
+            index_partition_size: None,
+            last_separator: Vec::default(),
+            partition_first_block: 0,
+            num_of_blocks: 0,
             block_builder: BlockBuilder::new(block_size),
-            start_key: Vec::default(),
+            last_key: Vec::default(),
             block_size,
+            properties: TableProperties {
+                block_size: block_size as u64,
+                comparator: comparator.name(),
+                ..TableProperties::default()
+            },
+            range_tombstones: Vec::new(),
+            comparator,
+        }
+    }
+
+    /// Switch to a partitioned index, see `with_partitioned_index`. Must be called before any key
+    /// is added.
+    pub fn set_index_partition_size(&mut self, index_partition_size: usize) {
+        assert_eq!(self.num_of_blocks, 0, "index partitioning must be set before adding keys");
+        self.index_partition_size = Some(index_partition_size);
+        self.properties.index_partition_size = index_partition_size as u64;
+    }
+
+    /// Sync a streamed table to disk every `bytes_per_sync` bytes instead of only when it is built,
+    /// which smooths out the I/O of writing large tables. Ignored by in-memory builders.
+    pub fn set_bytes_per_sync(&mut self, bytes_per_sync: u64) {
+        if let TableWriter::File(ref mut writer) = self.writer {
+            writer.bytes_per_sync = Some(bytes_per_sync);
         }
     }
 
+    /// Record the sequence number range of the entries in the table properties.
+    pub fn set_seq_range(&mut self, min_seq: u64, max_seq: u64) {
+        self.properties.min_seq = min_seq;
+        self.properties.max_seq = max_seq;
+    }
+
+    /// Get the comparator the table is ordered by.
+    pub fn comparator(&self) -> &C {
+        &self.comparator
+    }
+
     /// Adds a key-value pair to SSTable.
     /// Note: You should split a new block when the current block is full.(`std::mem::replace` may be of help here)
     pub fn add(&mut self, key: &[u8], value: &[u8]) {
-        if self.start_key.is_empty() {
-            self.start_key.put(key);
-        }
-
         let ok = self.block_builder.add(key, value);
         if !ok {
-            self.finish_block();
+            self.finish_block(Some(key));
             let ok = self.block_builder.add(key, value);
-            self.start_key.put(key);
             assert!(ok);
         }
+        self.last_key.clear();
+        self.last_key.put(key);
+        self.properties.add(key, value);
     }
 
-    fn finish_block(&mut self) {
+    /// Adds a range tombstone to the range deletion block. Tombstones may be added in any order and
+    /// delete keys of older tables only, never the keys of this table.
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.range_tombstones.push(tombstone);
+        self.properties.num_range_deletions += 1;
+    }
+
+    /// Flushes the current block and records it in the index. The index key is the shortest key
+    /// that separates the block from `next_key`, or a short successor of the last key if this is
+    /// the last block.
+    fn finish_block(&mut self, next_key: Option<&[u8]>) {
+        if self.block_builder.is_empty() {
+            return;
+        }
         let block = mem::replace(&mut self.block_builder, BlockBuilder::new(self.block_size));
-        let meta = BlockMeta { offset: self.data.len() as u64, first_key: mem::take(&mut self.start_key).into() };
-        self.data.put(block.build().encode());
-        //println!("encode table data{:?}, len {}", self.data, self.data.len());
-        self.meta.push(meta)
+        let handle = self.write_block(&block.build().encode());
+
+        let separator = match next_key {
+            Some(next_key) => self.comparator.find_shortest_separator(&self.last_key, next_key),
+            None => self.comparator.find_short_successor(&self.last_key),
+        };
+        let mut encoded_handle = Vec::with_capacity(BlockHandle::ENCODED_LEN);
+        handle.encode(&mut encoded_handle);
+        let ok = self.index_builder.add(&separator, &encoded_handle);
+        assert!(ok);
+        self.last_separator = separator;
+        self.num_of_blocks += 1;
+
+        if matches!(self.index_partition_size, Some(size) if self.index_builder.cur_size() >= size) {
+            self.finish_index_partition();
+        }
+    }
+
+    /// Writes the current index partition and records it in the top-level index.
+    fn finish_index_partition(&mut self) {
+        if self.index_builder.is_empty() {
+            return;
+        }
+        let partition = mem::replace(&mut self.index_builder, BlockBuilder::new_unbounded());
+        let handle = self.write_block(&partition.build().encode_wide());
+
+        let mut entry = Vec::with_capacity(BlockHandle::ENCODED_LEN + mem::size_of::<u64>());
+        handle.encode(&mut entry);
+        entry.put_u64(self.partition_first_block as u64);
+        let ok = self.top_index_builder.add(&self.last_separator, &entry);
+        assert!(ok);
+        self.partition_first_block = self.num_of_blocks;
+    }
+
+    fn write_block(&mut self, block: &[u8]) -> BlockHandle {
+        let offset = self.writer.offset();
+        self.writer.write(block);
+        BlockHandle { offset, size: block.len() as u64 }
     }
 
     /// Get the estimated size of the SSTable.
     /// Since the data blocks contain much more data than meta blocks, just return the size of data blocks here.
     pub fn estimated_size(&self) -> usize {
-        let mut size = self.data.len();
-        for meta in &self.meta {
-            size += meta.size()
-        }
-        size + mem::size_of::<u64>()
+        self.writer.offset() as usize + self.index_builder.cur_size() + self.top_index_builder.cur_size() + Footer::ENCODED_LEN
     }
 
     /// Builds the SSTable and writes it to the given path. No need to actually write to disk until
-    /// chapter 4 block cache.
+    /// chapter 4 block cache. A streaming builder must be given the path it was created with.
     pub fn build(
         mut self,
         id: usize,
         block_cache: Option<Arc<BlockCache>>,
         path: impl AsRef<Path>,
-    ) -> Result<SsTable> {
-        self.finish_block();
-        let meta_off = self.data.len() as u64;
-        BlockMeta::encode_block_meta(&self.meta, &mut self.data);
-        self.data.put_u64(meta_off);
-        let sst = SsTable {
-            file: FileObject::create(path.as_ref(), self.data)?,
-            block_metas: self.meta,
-            block_meta_offset: meta_off,
-            sst_id: id,
-            block_cache,
+    ) -> Result<SsTable<C>> {
+        self.finish_block(None);
+        let (index_type, index) = match self.index_partition_size {
+            None => (IndexType::Flat, mem::replace(&mut self.index_builder, BlockBuilder::new_unbounded())),
+            Some(_) => {
+                self.finish_index_partition();
+                (IndexType::Partitioned, mem::replace(&mut self.top_index_builder, BlockBuilder::new_unbounded()))
+            }
+        };
+        self.properties.finish(&self.last_key);
+        let range_del_handle = if self.range_tombstones.is_empty() {
+            BlockHandle::default()
+        } else {
+            self.write_block(&RangeTombstone::encode_block(&self.range_tombstones))
         };
+        let properties = self.properties.encode();
+        let properties_handle = self.write_block(&properties);
+        let index_handle = self.write_block(&index.build().encode_wide());
+        let footer = Footer {
+            range_del_handle,
+            properties_handle,
+            filter_handle: BlockHandle::default(),
+            index_handle,
+            index_type,
+            version: FORMAT_VERSION,
+        };
+        let mut raw_footer = Vec::with_capacity(Footer::ENCODED_LEN);
+        footer.encode(&mut raw_footer);
+        self.writer.write(&raw_footer);
+        let file = self.writer.finish(path.as_ref())?;
+        let sst = SsTable::from_index(
+            id,
+            file,
+            footer,
+            self.properties,
+            self.range_tombstones,
+            block_cache,
+            self.comparator,
+        )?;
         println!("table is {}", sst);
         Ok(sst)
     }
 
-    pub(crate) fn build_for_test(self, path: impl AsRef<Path>) -> Result<SsTable> {
+    pub(crate) fn build_for_test(self, path: impl AsRef<Path>) -> Result<SsTable<C>> {
         self.build(0, None, path)
     }
 }
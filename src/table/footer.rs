@@ -0,0 +1,107 @@
+use std::mem::size_of;
+
+use anyhow::{anyhow, Result};
+use bytes::{Buf, BufMut};
+
+use super::{BlockHandle, IndexType};
+
+/// Magic number at the end of every SsTable, "ELEGANCE" in ASCII.
+pub const TABLE_MAGIC: u64 = 0x454C_4547_414E_4345;
+
+/// The newest format version this build can read and the one it writes.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// The fixed-size trailer of an SsTable.
+///
+/// ---------------------------------------------------------------------------------------------------------------------------------------
+/// | Range Del Handle | Properties Handle | Filter Handle | Index Handle | Index Type (u8) | Version (u32) | Checksum (u32) | Magic (u64) |
+/// ---------------------------------------------------------------------------------------------------------------------------------------
+///
+/// The checksum is the crc32 of every footer byte before it. A filter or range deletion handle with
+/// size 0 means the table has no such block. The version, checksum and magic number always sit at
+/// the end, so files of another version are recognized before the rest of the footer is parsed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Footer {
+    pub range_del_handle: BlockHandle,
+    pub properties_handle: BlockHandle,
+    pub filter_handle: BlockHandle,
+    pub index_handle: BlockHandle,
+    pub index_type: IndexType,
+    pub version: u32,
+}
+
+impl Footer {
+    pub const ENCODED_LEN: usize = BlockHandle::ENCODED_LEN * 4
+        + size_of::<u8>()
+        + size_of::<u32>()
+        + size_of::<u32>()
+        + size_of::<u64>();
+
+    /// Encode the footer to a buffer.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        self.range_del_handle.encode(buf);
+        self.properties_handle.encode(buf);
+        self.filter_handle.encode(buf);
+        self.index_handle.encode(buf);
+        buf.put_u8(self.index_type as u8);
+        buf.put_u32(self.version);
+        let checksum = crc32fast::hash(&buf[start..]);
+        buf.put_u32(checksum);
+        buf.put_u64(TABLE_MAGIC);
+    }
+
+    /// Decode and validate the footer of a file of `file_len` bytes.
+    pub fn decode(data: &[u8], file_len: u64) -> Result<Self> {
+        if data.len() != Self::ENCODED_LEN {
+            return Err(anyhow!("sst footer must be {} bytes, got {}", Self::ENCODED_LEN, data.len()));
+        }
+        let magic = (&data[data.len() - size_of::<u64>()..]).get_u64();
+        if magic != TABLE_MAGIC {
+            return Err(anyhow!("not an sst file: bad magic number {:#x}", magic));
+        }
+        let checksum_off = data.len() - size_of::<u64>() - size_of::<u32>();
+        let version = (&data[checksum_off - size_of::<u32>()..]).get_u32();
+        if version > FORMAT_VERSION {
+            return Err(anyhow!("sst format version {} is newer than the supported version {}", version, FORMAT_VERSION));
+        }
+        if version < FORMAT_VERSION {
+            return Err(anyhow!("sst format version {} is older than the supported version {}", version, FORMAT_VERSION));
+        }
+        let checksum = (&data[checksum_off..]).get_u32();
+        if crc32fast::hash(&data[..checksum_off]) != checksum {
+            return Err(anyhow!("sst footer checksum mismatch"));
+        }
+
+        let mut buf = &data[..checksum_off];
+        let range_del_handle = BlockHandle::decode(&mut buf);
+        let properties_handle = BlockHandle::decode(&mut buf);
+        let filter_handle = BlockHandle::decode(&mut buf);
+        let index_handle = BlockHandle::decode(&mut buf);
+        let raw_index_type = buf.get_u8();
+        let index_type = IndexType::decode(raw_index_type)?;
+
+        let footer_off = file_len
+            .checked_sub(Self::ENCODED_LEN as u64)
+            .ok_or_else(|| anyhow!("sst file is truncated: {} bytes is shorter than the footer", file_len))?;
+        for (name, handle) in [
+            ("range deletion", range_del_handle),
+            ("properties", properties_handle),
+            ("filter", filter_handle),
+            ("index", index_handle),
+        ] {
+            let end = handle.offset.checked_add(handle.size);
+            if end.is_none_or(|end| end > footer_off) {
+                return Err(anyhow!("sst file is truncated: {} block {:?} is beyond {} bytes", name, handle, footer_off));
+            }
+        }
+        Ok(Self {
+            range_del_handle,
+            properties_handle,
+            filter_handle,
+            index_handle,
+            index_type,
+            version,
+        })
+    }
+}
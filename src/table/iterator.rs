@@ -5,22 +5,24 @@ use std::sync::Arc;
 
 use anyhow::Result;
 
+use crate::block::Block;
 use crate::block::iterator::BlockIterator;
 use crate::iterators::StorageIterator;
+use crate::skip_list::{BytewiseComparator, KeyComparator};
 
 use super::SsTable;
 
 /// An iterator over the contents of an SSTable.
-pub struct SsTableIterator {
-    table: Arc<SsTable>,
+pub struct SsTableIterator<C: KeyComparator = BytewiseComparator> {
+    table: Arc<SsTable<C>>,
     block_iterator: BlockIterator,
     //current block
     block_idx: usize,
 }
 
-impl SsTableIterator {
+impl<C: KeyComparator> SsTableIterator<C> {
     /// Create a new iterator and seek to the first key-value pair in the first data block.
-    pub fn create_and_seek_to_first(table: Arc<SsTable>) -> Result<Self> {
+    pub fn create_and_seek_to_first(table: Arc<SsTable<C>>) -> Result<Self> {
         let block_it = Self::seek_to_first_inner(&table)?;
         Ok(Self {
             table,
@@ -28,14 +30,32 @@ impl SsTableIterator {
             block_idx: 0,
         })
     }
-    fn seek_to_first_inner(table: &Arc<SsTable>) -> Result<BlockIterator> {
+    fn seek_to_first_inner(table: &Arc<SsTable<C>>) -> Result<BlockIterator> {
+        if table.num_of_blocks() == 0 {
+            return Ok(Self::empty_block_iterator());
+        }
         Ok(BlockIterator::create_and_seek_to_first(table.read_block_cached(0)?))
     }
 
-    fn seek_to_key_inner(table: &Arc<SsTable>, key: &[u8]) -> Result<BlockIterator> {
-        let idx = table.find_block_idx(key);
+    /// An invalid iterator, for tables without data blocks, e.g. with only range tombstones.
+    fn empty_block_iterator() -> BlockIterator {
+        BlockIterator::create_and_seek_to_first(Arc::new(Block::default()))
+    }
+
+    /// Seek to the first key >= `key`. Keys between the last key of a block and its separator fall
+    /// into that block, so the iterator may need to move on to the next one.
+    fn seek_to_key_inner(table: &Arc<SsTable<C>>, key: &[u8]) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, Self::empty_block_iterator()));
+        }
+        let mut idx = table.find_block_idx(key)?;
         let block = table.read_block_cached(idx)?;
-        Ok(BlockIterator::create_and_seek_to_first(block))
+        let mut block_it = BlockIterator::create_and_seek_to_key_with(block, key, table.comparator());
+        if !block_it.is_valid() && idx + 1 < table.num_of_blocks() {
+            idx += 1;
+            block_it = BlockIterator::create_and_seek_to_first(table.read_block_cached(idx)?);
+        }
+        Ok((idx, block_it))
     }
     /// Seek to the first key-value pair in the first data block.
     pub fn seek_to_first(&mut self) -> Result<()> {
@@ -45,10 +65,8 @@ impl SsTableIterator {
     }
 
     /// Create a new iterator and seek to the first key-value pair which >= `key`.
-    pub fn create_and_seek_to_key(table: Arc<SsTable>, key: &[u8]) -> Result<Self> {
-        let block_idx = table.find_block_idx(key);
-        let block = table.read_block_cached(block_idx)?;
-        let block_it = BlockIterator::create_and_seek_to_key(block, key);
+    pub fn create_and_seek_to_key(table: Arc<SsTable<C>>, key: &[u8]) -> Result<Self> {
+        let (block_idx, block_it) = Self::seek_to_key_inner(&table, key)?;
         Ok(Self {
             table,
             block_iterator: block_it,
@@ -59,15 +77,14 @@ impl SsTableIterator {
     /// Seek to the first key-value pair which >= `key`.
     /// Note: You probably want to review the handout for detailed explanation when implementing this function.
     pub fn seek_to_key(&mut self, key: &[u8]) -> Result<()> {
-        let idx = self.table.find_block_idx(key);
-        let block = self.table.read_block_cached(idx)?;
-        self.block_iterator = BlockIterator::create_and_seek_to_key(block, key);
+        let (idx, block_it) = Self::seek_to_key_inner(&self.table, key)?;
+        self.block_iterator = block_it;
         self.block_idx = idx;
         Ok(())
     }
 }
 
-impl StorageIterator for SsTableIterator {
+impl<C: KeyComparator> StorageIterator for SsTableIterator<C> {
     /// Return the `value` that's held by the underlying block iterator.
     fn value(&self) -> &[u8] {
         self.block_iterator.value()
@@ -0,0 +1,141 @@
+use std::cmp::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use bytes::{Buf, Bytes};
+
+use crate::block::block_builder::BlockBuilder;
+use crate::block::Block;
+use crate::skip_list::KeyComparator;
+
+const SMALLEST_KEY: &[u8] = b"smallest_key";
+const LARGEST_KEY: &[u8] = b"largest_key";
+const NUM_ENTRIES: &[u8] = b"num_entries";
+const NUM_DELETIONS: &[u8] = b"num_deletions";
+const NUM_RANGE_DELETIONS: &[u8] = b"num_range_deletions";
+const RAW_KEY_SIZE: &[u8] = b"raw_key_size";
+const RAW_VALUE_SIZE: &[u8] = b"raw_value_size";
+const MIN_SEQ: &[u8] = b"min_seq";
+const MAX_SEQ: &[u8] = b"max_seq";
+const CREATION_TIME: &[u8] = b"creation_time";
+const BLOCK_SIZE: &[u8] = b"block_size";
+const INDEX_PARTITION_SIZE: &[u8] = b"index_partition_size";
+const COMPARATOR: &[u8] = b"comparator";
+
+/// Statistics of an SsTable, collected by the builder and stored in the properties block.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TableProperties {
+    pub smallest_key: Bytes,
+    pub largest_key: Bytes,
+    pub num_entries: u64,
+    /// Number of entries with an empty value, which is how a delete is written.
+    pub num_deletions: u64,
+    /// Number of range tombstones in the range deletion block.
+    pub num_range_deletions: u64,
+    pub raw_key_size: u64,
+    pub raw_value_size: u64,
+    /// The sequence number range of the entries, as set by the writer of the table.
+    pub min_seq: u64,
+    pub max_seq: u64,
+    /// Seconds since the UNIX epoch at which the table was built.
+    pub creation_time: u64,
+    /// Target size of the data blocks.
+    pub block_size: u64,
+    /// Target size of the index partitions, 0 if the index is not partitioned.
+    pub index_partition_size: u64,
+    /// Name of the comparator the table is ordered by.
+    pub comparator: String,
+}
+
+impl TableProperties {
+    /// Account a key-value pair added to the table. Keys must be added in order.
+    pub(super) fn add(&mut self, key: &[u8], value: &[u8]) {
+        if self.num_entries == 0 {
+            self.smallest_key = Bytes::copy_from_slice(key);
+        }
+        self.num_entries += 1;
+        if value.is_empty() {
+            self.num_deletions += 1;
+        }
+        self.raw_key_size += key.len() as u64;
+        self.raw_value_size += value.len() as u64;
+    }
+
+    /// Finish collecting once all entries are added.
+    pub(super) fn finish(&mut self, largest_key: &[u8]) {
+        self.largest_key = Bytes::copy_from_slice(largest_key);
+        self.creation_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+    }
+
+    /// Returns whether the table may contain keys in `[smallest, largest]`.
+    pub fn overlaps<C: KeyComparator>(&self, c: &C, smallest: &[u8], largest: &[u8]) -> bool {
+        self.num_entries > 0
+            && c.compare_key(&self.smallest_key, largest) != Ordering::Greater
+            && c.compare_key(&self.largest_key, smallest) != Ordering::Less
+    }
+
+    /// Encode the properties as a block of `name -> value` entries.
+    pub fn encode(&self) -> Bytes {
+        let mut builder = BlockBuilder::new_unbounded();
+        let mut add = |name: &[u8], value: &[u8]| {
+            let ok = builder.add(name, value);
+            assert!(ok);
+        };
+        add(SMALLEST_KEY, &self.smallest_key);
+        add(LARGEST_KEY, &self.largest_key);
+        add(NUM_ENTRIES, &self.num_entries.to_be_bytes());
+        add(NUM_DELETIONS, &self.num_deletions.to_be_bytes());
+        add(NUM_RANGE_DELETIONS, &self.num_range_deletions.to_be_bytes());
+        add(RAW_KEY_SIZE, &self.raw_key_size.to_be_bytes());
+        add(RAW_VALUE_SIZE, &self.raw_value_size.to_be_bytes());
+        add(MIN_SEQ, &self.min_seq.to_be_bytes());
+        add(MAX_SEQ, &self.max_seq.to_be_bytes());
+        add(CREATION_TIME, &self.creation_time.to_be_bytes());
+        add(BLOCK_SIZE, &self.block_size.to_be_bytes());
+        add(INDEX_PARTITION_SIZE, &self.index_partition_size.to_be_bytes());
+        add(COMPARATOR, self.comparator.as_bytes());
+        builder.build().encode_wide()
+    }
+
+    /// Decode the properties from a properties block. Unknown names are skipped.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let block = Block::decode_wide(data);
+        let mut props = Self::default();
+        for idx in 0..block.num_of_entries() {
+            let (name, value) = (block.key_at(idx), block.value_at(idx));
+            let field = match name {
+                SMALLEST_KEY => {
+                    props.smallest_key = Bytes::copy_from_slice(value);
+                    continue;
+                }
+                LARGEST_KEY => {
+                    props.largest_key = Bytes::copy_from_slice(value);
+                    continue;
+                }
+                COMPARATOR => {
+                    props.comparator = String::from_utf8(value.to_vec())
+                        .map_err(|_| anyhow!("comparator name is not utf-8"))?;
+                    continue;
+                }
+                NUM_ENTRIES => &mut props.num_entries,
+                NUM_DELETIONS => &mut props.num_deletions,
+                NUM_RANGE_DELETIONS => &mut props.num_range_deletions,
+                RAW_KEY_SIZE => &mut props.raw_key_size,
+                RAW_VALUE_SIZE => &mut props.raw_value_size,
+                MIN_SEQ => &mut props.min_seq,
+                MAX_SEQ => &mut props.max_seq,
+                CREATION_TIME => &mut props.creation_time,
+                BLOCK_SIZE => &mut props.block_size,
+                INDEX_PARTITION_SIZE => &mut props.index_partition_size,
+                _ => continue,
+            };
+            if value.len() != 8 {
+                return Err(anyhow!("bad length {} of table property {:?}", value.len(), Bytes::copy_from_slice(name)));
+            }
+            *field = (&value[..]).get_u64();
+        }
+        Ok(props)
+    }
+}
@@ -0,0 +1,116 @@
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+
+use crate::iterators::StorageIterator;
+use crate::range_tombstone::RangeTombstone;
+use crate::skip_list::{BytewiseComparator, KeyComparator};
+
+use super::builder::SsTableBuilder;
+use super::iterator::SsTableIterator;
+use super::{FileObject, SsTable, TableProperties};
+
+/// Writes an SsTable outside of the engine, e.g. from an offline batch job, so that it can be
+/// ingested later. Keys must be added in strictly increasing order of the comparator `C`.
+pub struct SstFileWriter<C: KeyComparator = BytewiseComparator> {
+    builder: SsTableBuilder<C>,
+    path: PathBuf,
+    last_key: Vec<u8>,
+    num_range_deletions: usize,
+}
+
+impl SstFileWriter {
+    /// Create a writer that streams the table to `path`.
+    pub fn create(path: impl AsRef<Path>, block_size: usize) -> Result<Self> {
+        Self::create_with_comparator(path, block_size, BytewiseComparator)
+    }
+}
+
+impl<C: KeyComparator> SstFileWriter<C> {
+    /// Like `create`, for keys ordered by `comparator`.
+    pub fn create_with_comparator(path: impl AsRef<Path>, block_size: usize, comparator: C) -> Result<Self> {
+        Ok(Self {
+            builder: SsTableBuilder::create_with_comparator(path.as_ref(), block_size, comparator)?,
+            path: path.as_ref().to_path_buf(),
+            last_key: Vec::new(),
+            num_range_deletions: 0,
+        })
+    }
+
+    /// Add a key-value pair. Fails if `key` is not greater than the previously added key.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        if key.is_empty() {
+            return Err(anyhow!("empty key"));
+        }
+        if key.len() > u16::MAX as usize || value.len() > u16::MAX as usize {
+            return Err(anyhow!("key or value is longer than {} bytes", u16::MAX));
+        }
+        let c = self.builder.comparator();
+        if !self.last_key.is_empty() && c.compare_key(key, &self.last_key) != Ordering::Greater {
+            return Err(anyhow!("keys must be added in increasing order"));
+        }
+        self.builder.add(key, value);
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+        Ok(())
+    }
+
+    /// Add a deletion of `key`, written as an empty value.
+    pub fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.put(key, &[])
+    }
+
+    /// Add a range tombstone deleting `[start, end)` from the tables older than this one.
+    pub fn delete_range(&mut self, start: &[u8], end: &[u8]) -> Result<()> {
+        let tombstone = RangeTombstone::new(start, end);
+        tombstone.validate(self.builder.comparator())?;
+        self.builder.add_range_tombstone(tombstone);
+        self.num_range_deletions += 1;
+        Ok(())
+    }
+
+    /// Finish the file and return its properties.
+    pub fn finish(self) -> Result<TableProperties> {
+        if self.last_key.is_empty() && self.num_range_deletions == 0 {
+            return Err(anyhow!("cannot finish an sst file without entries"));
+        }
+        let sst = self.builder.build(0, None, &self.path)?;
+        Ok(sst.properties().clone())
+    }
+}
+
+/// Check that an external SsTable can be ingested: its footer must be valid, its keys strictly
+/// increasing in the order of `comparator` and consistent with its properties. Returns the
+/// properties of the file.
+pub fn validate_external_file<C: KeyComparator>(path: impl AsRef<Path>, comparator: C) -> Result<TableProperties> {
+    let file = FileObject::open(path.as_ref())?;
+    let sst = Arc::new(SsTable::open_with_comparator(0, file, None, comparator)?);
+    let props = sst.properties().clone();
+    if sst.num_of_blocks() == 0 {
+        if props.num_entries > 0 || sst.range_tombstones().is_empty() {
+            return Err(anyhow!("external sst {:?} has no entries", path.as_ref()));
+        }
+        return Ok(props);
+    }
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone())?;
+    let mut last_key: Vec<u8> = Vec::new();
+    let mut num_entries = 0;
+    while iter.is_valid() {
+        if num_entries == 0 && iter.key() != &props.smallest_key[..] {
+            return Err(anyhow!("external sst {:?} does not start at its smallest key", path.as_ref()));
+        }
+        if num_entries > 0 && sst.comparator().compare_key(iter.key(), &last_key) != Ordering::Greater {
+            return Err(anyhow!("keys of external sst {:?} are out of order", path.as_ref()));
+        }
+        last_key.clear();
+        last_key.extend_from_slice(iter.key());
+        num_entries += 1;
+        iter.next()?;
+    }
+    if num_entries != props.num_entries || last_key != props.largest_key {
+        return Err(anyhow!("external sst {:?} does not match its properties", path.as_ref()));
+    }
+    Ok(props)
+}
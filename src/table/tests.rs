@@ -4,6 +4,8 @@ use bytes::Bytes;
 use tempfile::{tempdir, TempDir};
 
 use crate::iterators::StorageIterator;
+use crate::range_tombstone::RangeTombstone;
+use crate::skip_list::{BytewiseComparator, KeyComparator};
 use crate::table::builder::SsTableBuilder;
 use crate::table::iterator::SsTableIterator;
 
@@ -26,7 +28,7 @@ fn test_sst_build_two_blocks() {
     builder.add(b"44", b"22");
     builder.add(b"55", b"11");
     builder.add(b"66", b"22");
-    assert!(builder.meta.len() >= 2);
+    assert!(builder.num_of_blocks >= 2);
     let dir = tempdir().unwrap();
     builder.build_for_test(dir.path().join("1.sst")).unwrap();
 }
@@ -63,9 +65,11 @@ fn test_sst_build_all() {
 #[test]
 fn test_sst_decode() {
     let (_dir, sst) = generate_sst();
-    let meta = sst.block_metas.clone();
+    let index_handle = sst.index_handle;
+    let num_of_blocks = sst.num_of_blocks();
     let new_sst = SsTable::open_for_test(sst.file).unwrap();
-    assert_eq!(new_sst.block_metas, meta);
+    assert_eq!(new_sst.index_handle, index_handle);
+    assert_eq!(new_sst.num_of_blocks(), num_of_blocks);
 }
 
 fn as_bytes(x: &[u8]) -> Bytes {
@@ -131,3 +135,365 @@ fn test_sst_seek_key() {
         iter.seek_to_key(b"k").unwrap();
     }
 }
+
+#[test]
+fn test_sst_find_block_idx() {
+    let (_dir, sst) = generate_sst();
+    for i in 0..num_of_keys() {
+        let idx = sst.find_block_idx(&key_of(i)).unwrap();
+        let block = sst.read_block(idx).unwrap();
+        let keys: Vec<&[u8]> = (0..block.num_of_entries()).map(|j| block.key_at(j)).collect();
+        assert!(keys.contains(&&key_of(i)[..]), "key {} not in block {}", i, idx);
+    }
+    assert_eq!(sst.find_block_idx(b"z").unwrap(), sst.num_of_blocks() - 1);
+}
+
+#[test]
+fn test_sst_index_cached() {
+    let mut builder = SsTableBuilder::new(48);
+    for idx in 0..num_of_keys() {
+        builder.add(&key_of(idx), &value_of(idx));
+    }
+    let dir = tempdir().unwrap();
+    let cache = Arc::new(BlockCache::new(16));
+    let sst = Arc::new(builder.build(1, Some(cache.clone()), dir.path().join("1.sst")).unwrap());
+    assert!(sst.index_block.is_none());
+    let mut iter = SsTableIterator::create_and_seek_to_key(sst.clone(), &key_of(42)).unwrap();
+    assert_eq!(iter.key(), key_of(42));
+    // Keys between a block's last key and its separator continue into the next block.
+    iter.seek_to_key(&format!("key_{:03}a", 42).into_bytes()).unwrap();
+    assert_eq!(iter.key(), key_of(43));
+    assert!(cache.contains_key(&(1, sst.index_handle.offset as usize)));
+}
+
+#[test]
+fn test_shortest_separator() {
+    let c = BytewiseComparator;
+    assert_eq!(c.find_shortest_separator(b"abc", b"abe"), b"abd");
+    assert_eq!(c.find_shortest_separator(b"abc", b"abd"), b"abc");
+    assert_eq!(c.find_shortest_separator(b"ab", b"abc"), b"ab");
+    assert_eq!(c.find_shortest_separator(b"a\xffz", b"b"), b"a\xffz");
+    assert_eq!(c.find_short_successor(b"abc"), b"b");
+    assert_eq!(c.find_short_successor(b"\xff\xffa"), b"\xff\xffb");
+    assert_eq!(c.find_short_successor(b"\xff"), b"\xff");
+}
+
+fn generate_partitioned_sst(block_cache: Option<Arc<BlockCache>>) -> (TempDir, SsTable) {
+    let mut builder = SsTableBuilder::with_partitioned_index(48, 64);
+    for idx in 0..num_of_keys() {
+        builder.add(&key_of(idx), &value_of(idx));
+    }
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let sst = builder.build(1, block_cache, path).unwrap();
+    (dir, sst)
+}
+
+#[test]
+fn test_sst_partitioned_index() {
+    let (_dir, sst) = generate_partitioned_sst(None);
+    assert_eq!(sst.index_type, IndexType::Partitioned);
+    let top_index = sst.read_index_block().unwrap();
+    assert!(top_index.num_of_entries() > 1);
+    assert!(top_index.num_of_entries() < sst.num_of_blocks());
+
+    let num_of_blocks = sst.num_of_blocks();
+    let sst = Arc::new(SsTable::open_for_test(sst.file).unwrap());
+    assert_eq!(sst.index_type, IndexType::Partitioned);
+    assert_eq!(sst.num_of_blocks(), num_of_blocks);
+
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    for i in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(i));
+        assert_eq!(iter.value(), value_of(i));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    for i in (0..num_of_keys()).rev() {
+        iter.seek_to_key(&key_of(i)).unwrap();
+        assert_eq!(iter.key(), key_of(i));
+    }
+}
+
+#[test]
+fn test_sst_partitioned_index_cached() {
+    let cache = Arc::new(BlockCache::new(1024));
+    let (_dir, sst) = generate_partitioned_sst(Some(cache.clone()));
+    assert!(sst.index_block.is_none());
+    let sst = Arc::new(sst);
+    let iter = SsTableIterator::create_and_seek_to_key(sst.clone(), &key_of(57)).unwrap();
+    assert_eq!(iter.key(), key_of(57));
+    cache.run_pending_tasks();
+    // Only the partition containing the key, the last partition read on open, the top-level
+    // index and the data block are cached.
+    assert!(cache.entry_count() <= 4);
+}
+
+#[test]
+fn test_sst_empty_partitioned_index() {
+    let dir = tempdir().unwrap();
+    let sst = SsTableBuilder::with_partitioned_index(48, 64).build_for_test(dir.path().join("1.sst")).unwrap();
+    assert_eq!(sst.num_of_blocks(), 0);
+    assert_eq!(sst.find_block_idx(b"key").unwrap(), 0);
+    let sst = SsTable::open_for_test(sst.file).unwrap();
+    assert_eq!(sst.index_type, IndexType::Partitioned);
+    assert_eq!(sst.find_block_idx(b"key").unwrap(), 0);
+}
+
+#[test]
+fn test_sst_properties() {
+    let mut builder = SsTableBuilder::new(48);
+    for idx in 0..num_of_keys() {
+        let value = if idx % 10 == 0 { vec![] } else { value_of(idx) };
+        builder.add(&key_of(idx), &value);
+    }
+    builder.set_seq_range(7, 42);
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    let props = sst.properties().clone();
+    assert_eq!(props.smallest_key, key_of(0));
+    assert_eq!(props.largest_key, key_of(num_of_keys() - 1));
+    assert_eq!(props.num_entries, num_of_keys() as u64);
+    assert_eq!(props.num_deletions, 10);
+    assert_eq!(props.raw_key_size, 7 * num_of_keys() as u64);
+    assert_eq!(props.raw_value_size, 9 * 90);
+    assert_eq!((props.min_seq, props.max_seq), (7, 42));
+    assert_eq!(props.block_size, 48);
+    assert_eq!(props.index_partition_size, 0);
+    assert!(props.creation_time > 0);
+
+    let new_sst = SsTable::open_for_test(sst.file).unwrap();
+    assert_eq!(new_sst.properties(), &props);
+    assert!(new_sst.overlaps(b"a", &key_of(0)));
+    assert!(new_sst.overlaps(&key_of(50), b"z"));
+    assert!(!new_sst.overlaps(b"a", b"b"));
+    assert!(!new_sst.overlaps(b"key_1000", b"z"));
+}
+
+fn open_raw(dir: &TempDir, data: Vec<u8>) -> Result<SsTable> {
+    let file = FileObject::create(&dir.path().join("raw.sst"), data).unwrap();
+    SsTable::open(0, file, None)
+}
+
+fn error_of(res: Result<SsTable>) -> String {
+    match res {
+        Ok(_) => panic!("open should fail"),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn test_sst_footer_validation() {
+    let (dir, sst) = generate_sst();
+    let len = sst.file.size();
+    let data = sst.file.read(0, len).unwrap();
+
+    let err = error_of(open_raw(&dir, data[..10].to_vec()));
+    assert!(err.contains("truncated"), "{}", err);
+
+    let err = error_of(open_raw(&dir, data[..data.len() - 1].to_vec()));
+    assert!(err.contains("bad magic"), "{}", err);
+
+    let err = error_of(open_raw(&dir, vec![0; 4096]));
+    assert!(err.contains("bad magic"), "{}", err);
+
+    // Drop the leading data block, so the handles point past the end of the file.
+    let err = error_of(open_raw(&dir, data[48..].to_vec()));
+    assert!(err.contains("truncated"), "{}", err);
+
+    let mut corrupted = data.clone();
+    let footer_off = corrupted.len() - Footer::ENCODED_LEN;
+    corrupted[footer_off] ^= 1;
+    let err = error_of(open_raw(&dir, corrupted));
+    assert!(err.contains("checksum"), "{}", err);
+
+    let err = Footer::decode(&data[footer_off..], Footer::ENCODED_LEN as u64 - 1).unwrap_err().to_string();
+    assert!(err.contains("truncated"), "{}", err);
+
+    let mut footer = Footer::decode(&data[footer_off..], len).unwrap();
+    footer.version = FORMAT_VERSION + 1;
+    let mut newer = data[..footer_off].to_vec();
+    footer.encode(&mut newer);
+    let err = error_of(open_raw(&dir, newer));
+    assert!(err.contains("newer"), "{}", err);
+
+    footer.version = FORMAT_VERSION - 1;
+    let mut older = data[..footer_off].to_vec();
+    footer.encode(&mut older);
+    let err = error_of(open_raw(&dir, older));
+    assert!(err.contains("older"), "{}", err);
+
+    assert!(open_raw(&dir, data).is_ok());
+}
+
+#[test]
+fn test_sst_streaming_builder() {
+    let dir = tempdir().unwrap();
+    let (_mem_dir, mem_sst) = generate_partitioned_sst(None);
+    let path = dir.path().join("streamed").join("2.sst");
+    let mut builder = SsTableBuilder::create(&path, 48).unwrap();
+    builder.set_index_partition_size(64);
+    builder.set_bytes_per_sync(256);
+    for idx in 0..num_of_keys() {
+        builder.add(&key_of(idx), &value_of(idx));
+    }
+    assert!(builder.estimated_size() > 0);
+    let sst = Arc::new(builder.build(2, None, &path).unwrap());
+
+    let mut expected = mem_sst.file.read(0, mem_sst.file.size()).unwrap();
+    let actual = sst.file.read(0, sst.file.size()).unwrap();
+    assert_eq!(expected.len(), actual.len());
+    // The files may only differ in the creation time recorded in the properties block.
+    let footer = Footer::decode(&expected[expected.len() - Footer::ENCODED_LEN..], expected.len() as u64).unwrap();
+    let mut props = mem_sst.properties().clone();
+    props.creation_time = sst.properties().creation_time;
+    let props_start = footer.properties_handle.offset as usize;
+    expected[props_start..props_start + footer.properties_handle.size as usize].copy_from_slice(&props.encode());
+    assert!(expected == actual);
+
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    for i in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(i));
+        iter.next().unwrap();
+    }
+}
+
+#[test]
+fn test_sst_file_writer() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("external.sst");
+    let mut writer = SstFileWriter::create(&path, 48).unwrap();
+    for idx in 0..num_of_keys() {
+        writer.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    assert!(writer.put(&key_of(10), b"v").is_err());
+    assert!(writer.put(&key_of(num_of_keys() - 1), b"v").is_err());
+    writer.delete(b"key_999").unwrap();
+    let props = writer.finish().unwrap();
+    assert_eq!(props.num_entries, num_of_keys() as u64 + 1);
+    assert_eq!(props.num_deletions, 1);
+
+    assert_eq!(validate_external_file(&path, BytewiseComparator).unwrap(), props);
+    let empty = SstFileWriter::create(dir.path().join("empty.sst"), 48).unwrap();
+    assert!(empty.finish().is_err());
+}
+
+#[test]
+fn test_validate_external_file() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("unordered.sst");
+    let mut builder = SsTableBuilder::new(48);
+    builder.add(&key_of(2), &value_of(2));
+    builder.add(&key_of(1), &value_of(1));
+    builder.build_for_test(&path).unwrap();
+    assert!(validate_external_file(&path, BytewiseComparator).is_err());
+
+    std::fs::write(&path, b"definitely not an sst file, but long enough to hold a footer of the table").unwrap();
+    assert!(validate_external_file(&path, BytewiseComparator).is_err());
+}
+
+#[test]
+fn test_sst_range_tombstones() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("range_del.sst");
+    let mut writer = SstFileWriter::create(&path, 48).unwrap();
+    writer.put(&key_of(1), &value_of(1)).unwrap();
+    assert!(writer.delete_range(b"b", b"a").is_err());
+    writer.delete_range(b"tenant_1/", b"tenant_10").unwrap();
+    writer.delete_range(b"a", b"b").unwrap();
+    let props = writer.finish().unwrap();
+    assert_eq!(props.num_range_deletions, 2);
+
+    let sst = SsTable::open(0, FileObject::open(&path).unwrap(), None).unwrap();
+    assert_eq!(
+        sst.range_tombstones(),
+        &[RangeTombstone::new(b"tenant_1/", b"tenant_10"), RangeTombstone::new(b"a", b"b")]
+    );
+
+    let path = dir.path().join("only_range_del.sst");
+    let mut writer = SstFileWriter::create(&path, 48).unwrap();
+    writer.delete_range(b"a", b"b").unwrap();
+    writer.finish().unwrap();
+    assert_eq!(validate_external_file(&path, BytewiseComparator).unwrap().num_range_deletions, 1);
+    let sst = Arc::new(SsTable::open(0, FileObject::open(&path).unwrap(), None).unwrap());
+    assert!(!SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap().is_valid());
+    let mut iter = SsTableIterator::create_and_seek_to_key(sst, b"a").unwrap();
+    assert!(!iter.is_valid());
+    iter.next().unwrap();
+    iter.seek_to_first().unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_range_tombstone_key_limits() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("range_del.sst");
+    let mut writer = SstFileWriter::create(&path, 48).unwrap();
+    let end = vec![b'b'; u16::MAX as usize];
+    assert!(writer.delete_range(b"a", &end).is_err());
+    writer.delete_range(b"a", &end[1..]).unwrap();
+    writer.finish().unwrap();
+    let sst = SsTable::open(0, FileObject::open(&path).unwrap(), None).unwrap();
+    assert_eq!(sst.range_tombstones(), &[RangeTombstone::new(b"a", &end[1..])]);
+}
+
+/// Orders keys in reverse byte order.
+#[derive(Clone, Copy)]
+struct ReverseComparator;
+
+impl KeyComparator for ReverseComparator {
+    fn compare_key(&self, lhs: &[u8], rhs: &[u8]) -> std::cmp::Ordering {
+        rhs.cmp(lhs)
+    }
+
+    fn same_key(&self, lhs: &[u8], rhs: &[u8]) -> bool {
+        lhs == rhs
+    }
+
+    fn name(&self) -> String {
+        "ReverseComparator".to_string()
+    }
+}
+
+#[test]
+fn test_sst_custom_comparator() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("reverse.sst");
+    let mut builder = SsTableBuilder::create_with_comparator(&path, 48, ReverseComparator).unwrap();
+    builder.set_index_partition_size(64);
+    for idx in (0..num_of_keys()).rev() {
+        builder.add(&key_of(idx), &value_of(idx));
+    }
+    let sst = builder.build(0, None, &path).unwrap();
+    assert_eq!(sst.properties().comparator, "ReverseComparator");
+    assert!(sst.overlaps(b"key_050", b"key_040"));
+    assert!(!sst.overlaps(b"a", b"\0"));
+    drop(sst);
+
+    let err = SsTable::open(0, FileObject::open(&path).unwrap(), None).err().unwrap();
+    assert!(err.to_string().contains("ReverseComparator"), "{}", err);
+
+    let file = FileObject::open(&path).unwrap();
+    let sst = Arc::new(SsTable::open_with_comparator(0, file, None, ReverseComparator).unwrap());
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    for i in (0..num_of_keys()).rev() {
+        assert_eq!(iter.key(), key_of(i));
+        iter.next().unwrap();
+    }
+    for i in 0..num_of_keys() {
+        iter.seek_to_key(&key_of(i)).unwrap();
+        assert_eq!(iter.key(), key_of(i));
+        // The first key <= "key_XXXa" in reverse order is key_XXX.
+        iter.seek_to_key(&format!("key_{:03}a", i).into_bytes()).unwrap();
+        assert_eq!(iter.key(), key_of(i));
+    }
+    assert!(validate_external_file(&path, ReverseComparator).is_ok());
+    assert!(validate_external_file(&path, BytewiseComparator).is_err());
+
+    let mut writer = SstFileWriter::create_with_comparator(dir.path().join("w.sst"), 48, ReverseComparator).unwrap();
+    writer.put(b"b", b"1").unwrap();
+    assert!(writer.put(b"c", b"1").is_err());
+    writer.put(b"a", b"1").unwrap();
+    assert!(writer.delete_range(b"a", b"b").is_err());
+    writer.delete_range(b"b", b"a").unwrap();
+}
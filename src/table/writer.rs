@@ -0,0 +1,107 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use super::FileObject;
+
+/// Where `SsTableBuilder` writes the encoded table.
+pub(super) enum TableWriter {
+    /// The table is buffered in memory and written to disk when it is built.
+    Memory(Vec<u8>),
+    /// The table is appended to a file as each block is finished.
+    File(StreamWriter),
+}
+
+impl TableWriter {
+    /// Number of bytes written so far, which is the offset of the next block.
+    pub(super) fn offset(&self) -> u64 {
+        match self {
+            TableWriter::Memory(data) => data.len() as u64,
+            TableWriter::File(writer) => writer.offset,
+        }
+    }
+
+    pub(super) fn write(&mut self, buf: &[u8]) {
+        match self {
+            TableWriter::Memory(data) => data.extend_from_slice(buf),
+            TableWriter::File(writer) => writer.write(buf),
+        }
+    }
+
+    /// Write out everything and open the finished file. `path` is only used by the in-memory writer,
+    /// the streaming writer must be finished at the path it was created with.
+    pub(super) fn finish(self, path: &Path) -> Result<FileObject> {
+        match self {
+            TableWriter::Memory(data) => FileObject::create(path, data),
+            TableWriter::File(writer) => {
+                if writer.path != path {
+                    return Err(anyhow!("sst is streamed to {:?}, cannot build it at {:?}", writer.path, path));
+                }
+                writer.finish()
+            }
+        }
+    }
+}
+
+/// Streams a table to a buffered file, optionally syncing it every `bytes_per_sync` bytes so that
+/// the OS never holds a large amount of dirty pages of the file.
+pub(super) struct StreamWriter {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    offset: u64,
+    synced_offset: u64,
+    pub(super) bytes_per_sync: Option<u64>,
+    /// The first I/O error. Once it is set, writes are dropped and `finish` returns it.
+    error: Option<std::io::Error>,
+}
+
+impl StreamWriter {
+    pub(super) fn create(path: &Path) -> Result<Self> {
+        if let Some(parent_dir) = path.parent() {
+            std::fs::create_dir_all(parent_dir)?;
+        }
+        let file = File::options().write(true).read(true).create(true).truncate(true).open(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer: BufWriter::new(file),
+            offset: 0,
+            synced_offset: 0,
+            bytes_per_sync: None,
+            error: None,
+        })
+    }
+
+    fn write(&mut self, buf: &[u8]) {
+        self.offset += buf.len() as u64;
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.write_inner(buf) {
+            self.error = Some(e);
+        }
+    }
+
+    fn write_inner(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(buf)?;
+        if let Some(bytes_per_sync) = self.bytes_per_sync {
+            if self.offset - self.synced_offset >= bytes_per_sync {
+                self.writer.flush()?;
+                self.writer.get_ref().sync_data()?;
+                self.synced_offset = self.offset;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<FileObject> {
+        if let Some(e) = self.error {
+            return Err(e.into());
+        }
+        self.writer.flush()?;
+        let file = self.writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_data()?;
+        Ok(FileObject(file))
+    }
+}